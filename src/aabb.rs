@@ -0,0 +1,141 @@
+use crate::{
+    ray::Ray,
+    triangle::Triangle,
+    vec3d::{length, Vec3D},
+};
+
+// Axis aligned bounding box. An empty box has `min` greater than `max` so that
+// expanding it by any point gives a box around just that point
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3D,
+    pub max: Vec3D,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3D, max: Vec3D) -> Self {
+        Self { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3D::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Vec3D::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_triangle(tri: &Triangle) -> Self {
+        let mut aabb = Aabb::empty();
+        for p in &tri.p {
+            aabb.expand(p);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn expand(&mut self, p: &Vec3D) {
+        self.min.x = self.min.x.min(p.x);
+        self.min.y = self.min.y.min(p.y);
+        self.min.z = self.min.z.min(p.z);
+        self.max.x = self.max.x.max(p.x);
+        self.max.y = self.max.y.max(p.y);
+        self.max.z = self.max.z.max(p.z);
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut aabb = *self;
+        aabb.expand(&other.min);
+        aabb.expand(&other.max);
+        aabb
+    }
+
+    pub fn center(&self) -> Vec3D {
+        Vec3D::new(
+            (self.min.x + self.max.x) * 0.5,
+            (self.min.y + self.max.y) * 0.5,
+            (self.min.z + self.max.z) * 0.5,
+        )
+    }
+
+    pub fn size(&self) -> Vec3D {
+        &self.max - &self.min
+    }
+
+    // Sphere around the box, as a centre and radius
+    pub fn bounding_sphere(&self) -> (Vec3D, f64) {
+        (self.center(), length(&self.size()) * 0.5)
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+            && self.min.z <= other.max.z
+            && self.max.z >= other.min.z
+    }
+
+    pub fn contains(&self, p: &Vec3D) -> bool {
+        p.x >= self.min.x
+            && p.x <= self.max.x
+            && p.y >= self.min.y
+            && p.y <= self.max.y
+            && p.z >= self.min.z
+            && p.z <= self.max.z
+    }
+
+    // Distance from a point to the nearest point of the box, 0.0 inside it
+    pub fn distance(&self, p: &Vec3D) -> f64 {
+        let outside = Vec3D::new(
+            (self.min.x - p.x).max(p.x - self.max.x).max(0.0),
+            (self.min.y - p.y).max(p.y - self.max.y).max(0.0),
+            (self.min.z - p.z).max(p.z - self.max.z).max(0.0),
+        );
+        length(&outside)
+    }
+
+    // The eight corners, ordered so that bit 0 of the index picks x, bit 1
+    // picks y and bit 2 picks z from `max` instead of `min`
+    pub fn corners(&self) -> [Vec3D; 8] {
+        let mut corners = [Vec3D::empty(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            *corner = Vec3D::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+        }
+        corners
+    }
+
+    // Slab test. Returns the distance along the ray at which it enters the box,
+    // or 0.0 if the ray starts inside it
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<f64> {
+        let mut t_min: f64 = 0.0;
+        let mut t_max = f64::INFINITY;
+
+        let axes = [
+            (ray.origin.x, ray.dir.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.dir.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.dir.z, self.min.z, self.max.z),
+        ];
+        for (origin, dir, min, max) in axes {
+            let inv = 1.0 / dir;
+            let mut t0 = (min - origin) * inv;
+            let mut t1 = (max - origin) * inv;
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+}
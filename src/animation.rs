@@ -0,0 +1,250 @@
+use crate::{
+    mat4x4::{
+        make_identity, make_rotation_x, make_rotation_y, make_rotation_z, make_scale,
+        make_translation, multiply_matrix, Mat4x4,
+    },
+    vec3d::Vec3D,
+    vertex::Interpolate,
+};
+
+// How a track fills in the values between its keyframes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    // Hold each key's value until the next key
+    Step,
+    Linear,
+    // A Catmull-Rom spline through the keys, which passes through every key
+    // with no sudden changes of direction
+    Cubic,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe<T> {
+    pub time: f64,
+    pub value: T,
+}
+
+// A value changing over time, given by keyframes kept sorted by time. Before
+// the first key and after the last the track holds the nearest key's value
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    pub keys: Vec<Keyframe<T>>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Interpolate + Copy> Track<T> {
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self {
+            keys: vec![],
+            interpolation,
+        }
+    }
+
+    // Add a key, replacing any key already at the same time
+    pub fn add(&mut self, time: f64, value: T) {
+        match self.keys.binary_search_by(|k| k.time.total_cmp(&time)) {
+            Ok(i) => self.keys[i].value = value,
+            Err(i) => self.keys.insert(i, Keyframe { time, value }),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // Time of the last key
+    pub fn duration(&self) -> f64 {
+        self.keys.last().map_or(0.0, |k| k.time)
+    }
+
+    pub fn sample(&self, time: f64) -> Option<T> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+
+        // Keys `i` and `i + 1` are either side of `time`
+        let i = self.keys.partition_point(|k| k.time <= time) - 1;
+        let (k1, k2) = (&self.keys[i], &self.keys[i + 1]);
+        let t = (time - k1.time) / (k2.time - k1.time);
+
+        let value = match self.interpolation {
+            Interpolation::Step => k1.value,
+            Interpolation::Linear => k1.value.lerp(&k2.value, t),
+            Interpolation::Cubic => {
+                // The end segments repeat their outer key, mirrored in time
+                let k0 = match i.checked_sub(1) {
+                    Some(j) => self.keys[j],
+                    None => Keyframe {
+                        time: 2.0 * k1.time - k2.time,
+                        value: k1.value,
+                    },
+                };
+                let k3 = match self.keys.get(i + 2) {
+                    Some(k) => *k,
+                    None => Keyframe {
+                        time: 2.0 * k2.time - k1.time,
+                        value: k2.value,
+                    },
+                };
+                catmull_rom([&k0, k1, k2, &k3], time)
+            }
+        };
+        Some(value)
+    }
+}
+
+// Evaluate a Catmull-Rom spline between `k[1]` and `k[2]` by repeated linear
+// interpolation (the Barry-Goldman pyramid). Using the key times as the knots
+// keeps the speed even when keys are unevenly spaced, and needs nothing more of
+// the value type than `Interpolate`
+fn catmull_rom<T: Interpolate>(k: [&Keyframe<T>; 4], time: f64) -> T {
+    let lerp = |a: &T, b: &T, t0: f64, t1: f64| a.lerp(b, (time - t0) / (t1 - t0));
+    let [t0, t1, t2, t3] = k.map(|k| k.time);
+
+    let a1 = lerp(&k[0].value, &k[1].value, t0, t1);
+    let a2 = lerp(&k[1].value, &k[2].value, t1, t2);
+    let a3 = lerp(&k[2].value, &k[3].value, t2, t3);
+
+    let b1 = lerp(&a1, &a2, t0, t2);
+    let b2 = lerp(&a2, &a3, t1, t3);
+
+    lerp(&b1, &b2, t1, t2)
+}
+
+// Keyframed placement of an object. Rotations are angles in radians about the
+// x, y and z axes. Empty tracks leave that part of the transform alone
+#[derive(Clone, Debug)]
+pub struct ObjectAnimation {
+    pub translation: Track<Vec3D>,
+    pub rotation: Track<[f64; 3]>,
+    pub scale: Track<[f64; 3]>,
+}
+
+impl ObjectAnimation {
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self {
+            translation: Track::new(interpolation),
+            rotation: Track::new(interpolation),
+            scale: Track::new(interpolation),
+        }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.translation
+            .duration()
+            .max(self.rotation.duration())
+            .max(self.scale.duration())
+    }
+
+    // World matrix at a time: scale, then rotate about x, y and z in turn, then
+    // translate
+    pub fn matrix(&self, time: f64) -> Mat4x4 {
+        let mut mat = make_identity();
+        if let Some([x, y, z]) = self.scale.sample(time) {
+            mat = multiply_matrix(&mat, &make_scale(x, y, z));
+        }
+        if let Some([x, y, z]) = self.rotation.sample(time) {
+            mat = multiply_matrix(&mat, &make_rotation_x(x));
+            mat = multiply_matrix(&mat, &make_rotation_y(y));
+            mat = multiply_matrix(&mat, &make_rotation_z(z));
+        }
+        if let Some(p) = self.translation.sample(time) {
+            mat = multiply_matrix(&mat, &make_translation(p.x, p.y, p.z));
+        }
+        mat
+    }
+}
+
+// Keyframed camera, given as where it is and the point it looks at
+#[derive(Clone, Debug)]
+pub struct CameraAnimation {
+    pub position: Track<Vec3D>,
+    pub target: Track<Vec3D>,
+}
+
+impl CameraAnimation {
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self {
+            position: Track::new(interpolation),
+            target: Track::new(interpolation),
+        }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.position.duration().max(self.target.duration())
+    }
+
+    // Position and target at a time, or None until both tracks have keys
+    pub fn sample(&self, time: f64) -> Option<(Vec3D, Vec3D)> {
+        Some((self.position.sample(time)?, self.target.sample(time)?))
+    }
+}
+
+// A playhead moving through an animation, which can be paused and moved to any
+// time by hand
+#[derive(Clone, Copy, Debug)]
+pub struct Playback {
+    pub time: f64,
+    pub duration: f64,
+    pub speed: f64,
+    pub playing: bool,
+    // Start again from the beginning at the end, rather than stopping
+    pub looping: bool,
+}
+
+impl Playback {
+    pub fn new(duration: f64) -> Self {
+        Self {
+            time: 0.0,
+            duration,
+            speed: 1.0,
+            playing: false,
+            looping: true,
+        }
+    }
+
+    pub fn play(&mut self) {
+        if !self.looping && self.time >= self.duration {
+            self.time = 0.0;
+        }
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn toggle(&mut self) {
+        if self.playing {
+            self.pause();
+        } else {
+            self.play();
+        }
+    }
+
+    // Move the playhead to a time, wrapped or clamped into the animation
+    pub fn seek(&mut self, time: f64) {
+        self.time = if self.looping && self.duration > 0.0 {
+            time.rem_euclid(self.duration)
+        } else {
+            time.clamp(0.0, self.duration)
+        };
+    }
+
+    // Move the playhead on by some seconds of real time, if playing
+    pub fn advance(&mut self, dt: f64) {
+        if !self.playing {
+            return;
+        }
+        let time = self.time + dt * self.speed;
+        if !self.looping && time >= self.duration {
+            self.playing = false;
+        }
+        self.seek(time);
+    }
+}
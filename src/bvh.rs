@@ -0,0 +1,432 @@
+use crate::{
+    aabb::Aabb,
+    frustum::{CullStats, Frustum, Visibility},
+    mesh::Mesh,
+    ray::{pick_triangle, Hit, Ray},
+    triangle::Triangle,
+    vec3d::{dot_product, Vec3D},
+};
+
+// Leaves stop splitting once they hold this many triangles or fewer
+const MAX_LEAF_SIZE: usize = 4;
+
+// Nodes are stored flat. An interior node's children are `left` and `left + 1`;
+// a leaf instead covers `count` entries of `Bvh::indices` starting at `first`
+#[derive(Clone, Copy, Debug)]
+pub struct BvhNode {
+    pub bounds: Aabb,
+    pub left: usize,
+    pub first: usize,
+    pub count: usize,
+}
+
+impl BvhNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+// Bounding volume hierarchy over the triangles of a mesh. It stores triangle
+// indices rather than triangles, so queries take the mesh it was built from,
+// and it must be rebuilt if that mesh changes
+#[derive(Clone)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    pub indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(mesh: &Mesh) -> Self {
+        let bounds = mesh
+            .tris
+            .iter()
+            .map(Aabb::from_triangle)
+            .collect::<Vec<_>>();
+        let centers = bounds.iter().map(|b| b.center()).collect::<Vec<_>>();
+
+        let mut bvh = Self {
+            nodes: vec![],
+            indices: (0..mesh.tris.len()).collect(),
+        };
+        if !bvh.indices.is_empty() {
+            bvh.nodes.push(BvhNode {
+                bounds: Aabb::empty(),
+                left: 0,
+                first: 0,
+                count: bvh.indices.len(),
+            });
+            bvh.subdivide(0, &bounds, &centers);
+        }
+        bvh
+    }
+
+    fn subdivide(&mut self, node: usize, bounds: &[Aabb], centers: &[Vec3D]) {
+        let first = self.nodes[node].first;
+        let count = self.nodes[node].count;
+        let indices = &mut self.indices[first..first + count];
+
+        let mut node_bounds = Aabb::empty();
+        let mut center_bounds = Aabb::empty();
+        for &i in indices.iter() {
+            node_bounds = node_bounds.union(&bounds[i]);
+            center_bounds.expand(&centers[i]);
+        }
+        self.nodes[node].bounds = node_bounds;
+
+        if count <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        // Split at the median along the axis where the triangle centres are
+        // most spread out
+        let size = center_bounds.size();
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+        let key = |i: &usize| -> f64 {
+            match axis {
+                0 => centers[*i].x,
+                1 => centers[*i].y,
+                _ => centers[*i].z,
+            }
+        };
+        let mid = count / 2;
+        indices.select_nth_unstable_by(mid, |a, b| key(a).total_cmp(&key(b)));
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounds: Aabb::empty(),
+            left: 0,
+            first,
+            count: mid,
+        });
+        self.nodes.push(BvhNode {
+            bounds: Aabb::empty(),
+            left: 0,
+            first: first + mid,
+            count: count - mid,
+        });
+        self.nodes[node].left = left;
+        self.nodes[node].count = 0;
+
+        self.subdivide(left, bounds, centers);
+        self.subdivide(left + 1, bounds, centers);
+    }
+
+    // Join BVHs built over runs of one mesh's triangles into a BVH over the
+    // whole mesh, with each part paired with the index of its first triangle.
+    // Only a tree over the parts' root boxes is built, so when a few parts
+    // change this is much cheaper than building the whole tree again
+    pub fn join(parts: &[(&Bvh, usize)]) -> Self {
+        let parts = parts
+            .iter()
+            .filter(|(part, _)| !part.nodes.is_empty())
+            .copied()
+            .collect::<Vec<_>>();
+
+        // Where each part's indices start in the joined list
+        let mut starts = vec![];
+        let mut bvh = Self {
+            nodes: vec![],
+            indices: vec![],
+        };
+        for (part, offset) in &parts {
+            starts.push(bvh.indices.len());
+            bvh.indices.extend(part.indices.iter().map(|i| i + offset));
+        }
+
+        if !parts.is_empty() {
+            bvh.nodes.push(BvhNode {
+                bounds: Aabb::empty(),
+                left: 0,
+                first: 0,
+                count: 0,
+            });
+            let mut order = (0..parts.len()).collect::<Vec<_>>();
+            bvh.join_node(0, &mut order, &parts, &starts);
+        }
+        bvh
+    }
+
+    // Fill `node` with a tree over the parts in `order`, split at the median
+    // the same way `subdivide` splits triangles, until one part is left. That
+    // part's nodes are then copied in with its root at `node`
+    fn join_node(
+        &mut self,
+        node: usize,
+        order: &mut [usize],
+        parts: &[(&Bvh, usize)],
+        starts: &[usize],
+    ) {
+        if let [part] = order[..] {
+            let base = self.nodes.len();
+            let place = |n: usize| if n == 0 { node } else { base + n - 1 };
+            for (n, part_node) in parts[part].0.nodes.iter().enumerate() {
+                let mut copy = *part_node;
+                copy.first += starts[part];
+                if !copy.is_leaf() {
+                    copy.left = place(copy.left);
+                }
+                if n == 0 {
+                    self.nodes[node] = copy;
+                } else {
+                    self.nodes.push(copy);
+                }
+            }
+            return;
+        }
+
+        let center = |i: &usize| parts[*i].0.bounds().center();
+        let mut center_bounds = Aabb::empty();
+        for i in order.iter() {
+            center_bounds.expand(&center(i));
+        }
+        let size = center_bounds.size();
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+        let key = |i: &usize| -> f64 {
+            let c = center(i);
+            match axis {
+                0 => c.x,
+                1 => c.y,
+                _ => c.z,
+            }
+        };
+        let mid = order.len() / 2;
+        order.select_nth_unstable_by(mid, |a, b| key(a).total_cmp(&key(b)));
+
+        let left = self.nodes.len();
+        for _ in 0..2 {
+            self.nodes.push(BvhNode {
+                bounds: Aabb::empty(),
+                left: 0,
+                first: 0,
+                count: 0,
+            });
+        }
+        let (a, b) = order.split_at_mut(mid);
+        self.join_node(left, a, parts, starts);
+        self.join_node(left + 1, b, parts, starts);
+        self.nodes[node] = BvhNode {
+            bounds: self.nodes[left].bounds.union(&self.nodes[left + 1].bounds),
+            left,
+            first: 0,
+            count: 0,
+        };
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::empty(), |n| n.bounds)
+    }
+
+    // Bounds of every node `depth` levels below the root, plus any leaves that
+    // end above that level. Used to draw the tree for debugging
+    pub fn boxes_at_depth(&self, depth: usize) -> Vec<Aabb> {
+        let mut boxes = vec![];
+        if self.nodes.is_empty() {
+            return boxes;
+        }
+
+        let mut stack = vec![(0, 0)];
+        while let Some((n, d)) = stack.pop() {
+            let node = &self.nodes[n];
+            if d == depth || node.is_leaf() {
+                boxes.push(node.bounds);
+            } else {
+                stack.push((node.left, d + 1));
+                stack.push((node.left + 1, d + 1));
+            }
+        }
+        boxes
+    }
+
+    // Walk the tree, descending into nodes whose bounds pass `test` and calling
+    // `leaf` with the index of every triangle in the leaves reached
+    pub fn traverse(&self, mut test: impl FnMut(&Aabb) -> bool, mut leaf: impl FnMut(usize)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if !test(&node.bounds) {
+                continue;
+            }
+            if node.is_leaf() {
+                for &i in &self.indices[node.first..node.first + node.count] {
+                    leaf(i);
+                }
+            } else {
+                stack.push(node.left);
+                stack.push(node.left + 1);
+            }
+        }
+    }
+
+    // Indices of triangles in chunks that are at least partly inside the
+    // frustum. Chunks found to be completely inside are not tested further
+    pub fn frustum_cull(&self, frustum: &Frustum, stats: &mut CullStats) -> Vec<usize> {
+        let mut visible = vec![];
+        if self.nodes.is_empty() {
+            return visible;
+        }
+
+        let mut stack = vec![(0, false)];
+        while let Some((n, inside)) = stack.pop() {
+            let node = &self.nodes[n];
+
+            let mut inside = inside;
+            if !inside {
+                stats.chunks_tested += 1;
+                match frustum.test_aabb(&node.bounds) {
+                    Visibility::Outside => {
+                        stats.chunks_culled += 1;
+                        continue;
+                    }
+                    Visibility::Inside => inside = true,
+                    Visibility::Intersecting => {}
+                }
+            }
+
+            if node.is_leaf() {
+                visible.extend_from_slice(&self.indices[node.first..node.first + node.count]);
+            } else {
+                stack.push((node.left, inside));
+                stack.push((node.left + 1, inside));
+            }
+        }
+
+        stats.triangles_culled += self.indices.len() - visible.len();
+        stats.triangles_visible += visible.len();
+        visible
+    }
+
+    // Same result as `ray::pick`, but only tests triangles in boxes the ray
+    // passes through, visiting the nearer child first
+    pub fn pick(&self, ray: &Ray, mesh: &Mesh) -> Option<Hit> {
+        let mut closest: Option<Hit> = None;
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            match node.bounds.intersect_ray(ray) {
+                Some(t) if closest.is_none_or(|c| t <= c.t) => {}
+                _ => continue,
+            }
+
+            if node.is_leaf() {
+                for &i in &self.indices[node.first..node.first + node.count] {
+                    if let Some(hit) = pick_triangle(ray, i, &mesh.tris[i]) {
+                        if closest.is_none_or(|c| hit.t < c.t) {
+                            closest = Some(hit);
+                        }
+                    }
+                }
+            } else {
+                let (a, b) = (node.left, node.left + 1);
+                let ta = self.nodes[a].bounds.intersect_ray(ray);
+                let tb = self.nodes[b].bounds.intersect_ray(ray);
+                // Push the farther child first so the nearer one is popped first
+                if ta.unwrap_or(f64::INFINITY) < tb.unwrap_or(f64::INFINITY) {
+                    stack.push(b);
+                    stack.push(a);
+                } else {
+                    stack.push(a);
+                    stack.push(b);
+                }
+            }
+        }
+
+        closest
+    }
+
+    // Indices of triangles whose bounding boxes overlap `bounds`. This is the
+    // broad phase for collision queries
+    pub fn query_aabb(&self, bounds: &Aabb) -> Vec<usize> {
+        let mut found = vec![];
+        self.traverse(|b| b.overlaps(bounds), |i| found.push(i));
+        found
+    }
+
+    // Does a sphere touch any triangle of the mesh?
+    pub fn intersects_sphere(&self, mesh: &Mesh, center: &Vec3D, radius: f64) -> bool {
+        let r = Vec3D::new(radius, radius, radius);
+        let bounds = Aabb::new(
+            Vec3D::new(center.x - r.x, center.y - r.y, center.z - r.z),
+            Vec3D::new(center.x + r.x, center.y + r.y, center.z + r.z),
+        );
+
+        self.query_aabb(&bounds).into_iter().any(|i| {
+            let closest = closest_point_on_triangle(center, &mesh.tris[i]);
+            let d = &closest - center;
+            dot_product(&d, &d) <= radius * radius
+        })
+    }
+}
+
+// Closest point on a triangle to `p`, found by checking which vertex, edge or
+// face region of the triangle `p` projects into
+pub fn closest_point_on_triangle(p: &Vec3D, tri: &Triangle) -> Vec3D {
+    let [a, b, c] = tri.p;
+    let ab = &b - &a;
+    let ac = &c - &a;
+    let ap = p - &a;
+
+    let d1 = dot_product(&ab, &ap);
+    let d2 = dot_product(&ac, &ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - &b;
+    let d3 = dot_product(&ab, &bp);
+    let d4 = dot_product(&ac, &bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return &a + &(&ab * v);
+    }
+
+    let cp = p - &c;
+    let d5 = dot_product(&ab, &cp);
+    let d6 = dot_product(&ac, &cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return &a + &(&ac * w);
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        let bc = &c - &b;
+        return &b + &(&bc * w);
+    }
+
+    // Inside the face
+    let denom = 1.0 / (va + vb + vc);
+    let v = vb * denom;
+    let w = vc * denom;
+    &(&a + &(&ab * v)) + &(&ac * w)
+}
@@ -0,0 +1,120 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use crate::vec3d::Vec3D;
+
+// The camera after one simulation step, and the length of that step in seconds
+#[derive(Clone, Copy, Debug)]
+pub struct CameraSample {
+    pub dt: f64,
+    pub position: Vec3D,
+    pub yaw: f64,
+    pub look_dir: Vec3D,
+}
+
+// A recorded camera path, saved as text with one sample per line. Numbers are
+// written so they read back exactly, making a replay match the recording
+#[derive(Clone, Debug, Default)]
+pub struct CameraPath {
+    pub samples: Vec<CameraSample>,
+}
+
+impl CameraPath {
+    pub fn new() -> Self {
+        Self { samples: vec![] }
+    }
+
+    pub fn push(&mut self, sample: CameraSample) {
+        self.samples.push(sample);
+    }
+
+    // Total time covered by the path
+    pub fn duration(&self) -> f64 {
+        self.samples.iter().map(|s| s.dt).sum()
+    }
+
+    pub fn save(&self, filename: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(filename)?);
+        writeln!(out, "# dt x y z yaw look_x look_y look_z")?;
+        for s in &self.samples {
+            writeln!(
+                out,
+                "{} {} {} {} {} {} {} {}",
+                s.dt,
+                s.position.x,
+                s.position.y,
+                s.position.z,
+                s.yaw,
+                s.look_dir.x,
+                s.look_dir.y,
+                s.look_dir.z
+            )?;
+        }
+        out.flush()
+    }
+
+    pub fn load(filename: impl AsRef<Path>) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(filename)?);
+        let mut path = Self::new();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let nums = line
+                .split_ascii_whitespace()
+                .map(|n| n.parse::<f64>().ok())
+                .collect::<Option<Vec<f64>>>()
+                .filter(|nums| nums.len() == 8)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {}: expected 8 numbers", i + 1),
+                    )
+                })?;
+
+            path.push(CameraSample {
+                dt: nums[0],
+                position: Vec3D::new(nums[1], nums[2], nums[3]),
+                yaw: nums[4],
+                look_dir: Vec3D::new(nums[5], nums[6], nums[7]),
+            });
+        }
+
+        Ok(path)
+    }
+}
+
+// Steps through a path one sample at a time
+#[derive(Clone, Debug)]
+pub struct CameraPathPlayer {
+    pub path: CameraPath,
+    next: usize,
+}
+
+impl CameraPathPlayer {
+    pub fn new(path: CameraPath) -> Self {
+        Self { path, next: 0 }
+    }
+
+    // Index of the sample `next_sample` will return
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.path.samples.len()
+    }
+
+    pub fn next_sample(&mut self) -> Option<CameraSample> {
+        let sample = *self.path.samples.get(self.next)?;
+        self.next += 1;
+        Some(sample)
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use image::{GrayImage, ImageResult, Luma, RgbaImage};
+
+use crate::{fog::view_depth, mat4x4::Mat4x4};
+
+// Copy a frame buffer into an image, forcing every pixel opaque so the saved
+// file looks the same as the window
+pub fn frame_image(frame: &[u8], canvas_width: i32) -> RgbaImage {
+    let canvas_height = frame.len() as i32 / 4 / canvas_width;
+    let mut pixels = frame.to_vec();
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel[3] = 0xff;
+    }
+    RgbaImage::from_raw(canvas_width as u32, canvas_height as u32, pixels).unwrap()
+}
+
+// Turn a depth buffer into a grayscale image. Distances are spread linearly
+// between the nearest and farthest drawn pixels, white being nearest, and
+// pixels left at the far plane are black
+pub fn depth_image(depth_buffer: &[f64], canvas_width: i32, mat_proj: &Mat4x4) -> GrayImage {
+    let canvas_height = depth_buffer.len() as i32 / canvas_width;
+    let distances = depth_buffer
+        .iter()
+        .map(|&depth| (depth < 1.0).then(|| view_depth(depth, mat_proj)))
+        .collect::<Vec<_>>();
+
+    let (near, far) = distances
+        .iter()
+        .flatten()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(near, far), &d| {
+            (near.min(d), far.max(d))
+        });
+    let range = (far - near).max(f64::EPSILON);
+
+    GrayImage::from_fn(canvas_width as u32, canvas_height as u32, |x, y| {
+        let i = (y * canvas_width as u32 + x) as usize;
+        let c = match distances[i] {
+            Some(d) => 255.0 - (d - near) / range * 223.0,
+            None => 0.0,
+        };
+        Luma([c as u8])
+    })
+}
+
+pub fn save_frame(frame: &[u8], canvas_width: i32, path: impl AsRef<Path>) -> ImageResult<()> {
+    frame_image(frame, canvas_width).save(path)
+}
+
+pub fn save_depth(
+    depth_buffer: &[f64],
+    canvas_width: i32,
+    mat_proj: &Mat4x4,
+    path: impl AsRef<Path>,
+) -> ImageResult<()> {
+    depth_image(depth_buffer, canvas_width, mat_proj).save(path)
+}
+
+// Writes frames to a numbered sequence of PNGs, `<prefix>_00000.png` and
+// onwards, with `<prefix>_00000_depth.png` next to each when `depth` is set
+pub struct FrameSequence {
+    pub directory: PathBuf,
+    pub prefix: String,
+    pub depth: bool,
+    next: usize,
+}
+
+impl FrameSequence {
+    // Numbering carries on after any frames already in the directory, so a
+    // new recording never overwrites an old one
+    pub fn new(directory: impl AsRef<Path>, prefix: &str, depth: bool) -> Self {
+        let directory = directory.as_ref().to_path_buf();
+        let next = next_free_index(&directory, prefix);
+        Self {
+            directory,
+            prefix: prefix.to_string(),
+            depth,
+            next,
+        }
+    }
+
+    // Number of the next frame to be written
+    pub fn next_index(&self) -> usize {
+        self.next
+    }
+
+    pub fn path(&self, index: usize) -> PathBuf {
+        self.directory
+            .join(format!("{}_{:05}.png", self.prefix, index))
+    }
+
+    pub fn depth_path(&self, index: usize) -> PathBuf {
+        self.directory
+            .join(format!("{}_{:05}_depth.png", self.prefix, index))
+    }
+
+    // Save the next frame, returning the path of the color image
+    pub fn write(
+        &mut self,
+        frame: &[u8],
+        depth_buffer: &[f64],
+        canvas_width: i32,
+        mat_proj: &Mat4x4,
+    ) -> ImageResult<PathBuf> {
+        fs::create_dir_all(&self.directory)?;
+
+        let path = self.path(self.next);
+        save_frame(frame, canvas_width, &path)?;
+        if self.depth {
+            save_depth(
+                depth_buffer,
+                canvas_width,
+                mat_proj,
+                self.depth_path(self.next),
+            )?;
+        }
+
+        self.next += 1;
+        Ok(path)
+    }
+}
+
+// One past the highest `<prefix>_NNNNN.png` in a directory
+fn next_free_index(directory: &Path, prefix: &str) -> usize {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let number = name.strip_prefix(prefix)?.strip_prefix('_')?;
+            number.strip_suffix(".png")?.parse::<usize>().ok()
+        })
+        .map(|index| index + 1)
+        .max()
+        .unwrap_or(0)
+}
@@ -0,0 +1,96 @@
+use crate::{
+    triangle::Triangle,
+    vec3d::Vec3D,
+    vertex::{Interpolate, Vertex},
+};
+
+// The six planes of the view volume in homogeneous clip space, as produced by
+// the projection matrices: -w <= x <= w, -w <= y <= w and 0 <= z <= w
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipPlane {
+    Left,
+    Right,
+    Bottom,
+    Top,
+    Near,
+    Far,
+}
+
+pub const CLIP_PLANES: [ClipPlane; 6] = [
+    ClipPlane::Near,
+    ClipPlane::Far,
+    ClipPlane::Left,
+    ClipPlane::Right,
+    ClipPlane::Bottom,
+    ClipPlane::Top,
+];
+
+impl ClipPlane {
+    // Signed distance of a clip space point from the plane, positive inside
+    pub fn distance(&self, p: &Vec3D) -> f64 {
+        match self {
+            ClipPlane::Left => p.w + p.x,
+            ClipPlane::Right => p.w - p.x,
+            ClipPlane::Bottom => p.w + p.y,
+            ClipPlane::Top => p.w - p.y,
+            ClipPlane::Near => p.z,
+            ClipPlane::Far => p.w - p.z,
+        }
+    }
+}
+
+// Clip a convex polygon against a plane given by a signed distance function,
+// keeping the parts with a positive distance (Sutherland-Hodgman). Every
+// attribute of the new vertices is interpolated by how far along its edge the
+// plane lies. The output keeps the winding of the input, and has no vertices
+// if the polygon is entirely outside
+pub fn clip_polygon<V: Interpolate + Copy>(polygon: &[V], distance: impl Fn(&V) -> f64) -> Vec<V> {
+    let mut out = Vec::with_capacity(polygon.len() + 1);
+    if polygon.is_empty() {
+        return out;
+    }
+
+    let mut prev = &polygon[polygon.len() - 1];
+    let mut prev_d = distance(prev);
+    for curr in polygon {
+        let curr_d = distance(curr);
+
+        // Add the point where the edge crosses the plane, if it does
+        if (prev_d >= 0.0) != (curr_d >= 0.0) {
+            out.push(prev.lerp(curr, prev_d / (prev_d - curr_d)));
+        }
+        if curr_d >= 0.0 {
+            out.push(*curr);
+        }
+
+        prev = curr;
+        prev_d = curr_d;
+    }
+
+    out
+}
+
+// Clip a clip space polygon against all six planes of the view volume. The
+// result is a convex polygon, or no vertices at all
+pub fn clip_to_view_volume(polygon: &[Vertex]) -> Vec<Vertex> {
+    let mut polygon = polygon.to_vec();
+
+    for plane in CLIP_PLANES {
+        // Skip the work if every vertex is already inside
+        if polygon.iter().all(|v| plane.distance(&v.p) >= 0.0) {
+            continue;
+        }
+        polygon = clip_polygon(&polygon, |v| plane.distance(&v.p));
+        if polygon.is_empty() {
+            break;
+        }
+    }
+
+    polygon
+}
+
+// Clip a clip space triangle against the view volume, splitting what is left
+// back into triangles
+pub fn clip_triangle(tri: &Triangle) -> Vec<Triangle> {
+    Triangle::fan(tri, &clip_to_view_volume(&tri.vertices()))
+}
@@ -0,0 +1,67 @@
+use crate::{
+    mat4x4::Mat4x4,
+    sky::{blend, ScreenRays, Sky},
+};
+
+// How fog thickens with distance from the camera
+#[derive(Clone, Copy, Debug)]
+pub enum Fog {
+    // No fog before `start`, fully fogged from `end`
+    Linear { start: f64, end: f64 },
+    Exponential { density: f64 },
+    ExponentialSquared { density: f64 },
+}
+
+impl Fog {
+    // Amount of fog at a distance, from 0.0 for none to 1.0 for fully fogged
+    pub fn factor(&self, distance: f64) -> f64 {
+        let f = match *self {
+            Fog::Linear { start, end } => (distance - start) / (end - start),
+            Fog::Exponential { density } => 1.0 - (-density * distance).exp(),
+            Fog::ExponentialSquared { density } => 1.0 - (-(density * distance).powi(2)).exp(),
+        };
+        f.clamp(0.0, 1.0)
+    }
+
+    // Blend every drawn pixel towards the sky behind it by its distance. This
+    // runs after the scene is drawn, reading distances back from the depth
+    // buffer, so pixels left at the far plane are sky and are left alone
+    pub fn apply(
+        &self,
+        frame: &mut [u8],
+        canvas_width: i32,
+        depth_buffer: &[f64],
+        sky: &Sky,
+        mat_proj: &Mat4x4,
+        mat_view_proj: &Mat4x4,
+    ) {
+        let canvas_height = frame.len() as i32 / 4 / canvas_width;
+        let rays = ScreenRays::new(canvas_width, canvas_height, mat_view_proj);
+
+        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+            let depth = depth_buffer[i];
+            if depth >= 1.0 {
+                continue;
+            }
+
+            let f = self.factor(view_depth(depth, mat_proj));
+            if f <= 0.0 {
+                continue;
+            }
+
+            let x = i as i32 % canvas_width;
+            let y = i as i32 / canvas_width;
+            let background = sky.color(&rays.direction(x, y));
+            let rgba = blend(&[pixel[0], pixel[1], pixel[2], pixel[3]], &background, f);
+            pixel.copy_from_slice(&rgba);
+        }
+    }
+}
+
+// Distance along the view direction of a point with a given depth buffer value,
+// found by inverting the projection's depth mapping. Works for both perspective
+// and orthographic projections
+pub fn view_depth(depth: f64, mat_proj: &Mat4x4) -> f64 {
+    let m = &mat_proj.m;
+    ((m[3][2] - depth * m[3][3]) / (depth * m[2][3] - m[2][2])).abs()
+}
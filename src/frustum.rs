@@ -0,0 +1,138 @@
+use crate::{
+    aabb::Aabb,
+    mat4x4::Mat4x4,
+    vec3d::{dot_product, length, Vec3D},
+};
+
+// Plane holding the points where `dot(n, p) + d = 0`. Points with a positive
+// distance lie on the inside
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub n: Vec3D,
+    pub d: f64,
+}
+
+impl Plane {
+    pub fn distance(&self, p: &Vec3D) -> f64 {
+        dot_product(&self.n, p) + self.d
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+// Counts from one frame of culling, for showing how much work was skipped
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CullStats {
+    pub objects_culled: usize,
+    pub chunks_tested: usize,
+    pub chunks_culled: usize,
+    pub triangles_culled: usize,
+    pub triangles_visible: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    // Extract the left, right, bottom, top, near and far planes from a combined
+    // world, view and projection matrix. The planes are in the space the matrix
+    // transforms from, so passing in the world matrix too gives planes in the
+    // local space of an object
+    pub fn from_matrix(m: &Mat4x4) -> Self {
+        // Vectors are multiplied as rows, so each clip space coordinate is the
+        // dot product with a column of the matrix
+        let col = |c: usize| [m.m[0][c], m.m[1][c], m.m[2][c], m.m[3][c]];
+        let (x, y, z, w) = (col(0), col(1), col(2), col(3));
+
+        let plane = |a: [f64; 4], b: [f64; 4], s: f64| -> Plane {
+            let n = Vec3D::new(a[0] + s * b[0], a[1] + s * b[1], a[2] + s * b[2]);
+            let l = length(&n);
+            Plane {
+                n: &n / l,
+                d: (a[3] + s * b[3]) / l,
+            }
+        };
+
+        // Depth runs from 0.0 to w, so the near plane is just z >= 0
+        Self {
+            planes: [
+                plane(w, x, 1.0),
+                plane(w, x, -1.0),
+                plane(w, y, 1.0),
+                plane(w, y, -1.0),
+                plane(z, z, 0.0),
+                plane(w, z, -1.0),
+            ],
+        }
+    }
+
+    pub fn test_sphere(&self, center: &Vec3D, radius: f64) -> Visibility {
+        let mut visibility = Visibility::Inside;
+        for plane in &self.planes {
+            let d = plane.distance(center);
+            if d < -radius {
+                return Visibility::Outside;
+            }
+            if d < radius {
+                visibility = Visibility::Intersecting;
+            }
+        }
+        visibility
+    }
+
+    pub fn test_aabb(&self, aabb: &Aabb) -> Visibility {
+        let mut visibility = Visibility::Inside;
+        for plane in &self.planes {
+            // The corner furthest along the plane normal is the last to leave
+            // the inside, and the nearest corner is the first
+            let far = Vec3D::new(
+                if plane.n.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.n.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.n.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            let near = Vec3D::new(
+                if plane.n.x >= 0.0 {
+                    aabb.min.x
+                } else {
+                    aabb.max.x
+                },
+                if plane.n.y >= 0.0 {
+                    aabb.min.y
+                } else {
+                    aabb.max.y
+                },
+                if plane.n.z >= 0.0 {
+                    aabb.min.z
+                } else {
+                    aabb.max.z
+                },
+            );
+            if plane.distance(&far) < 0.0 {
+                return Visibility::Outside;
+            }
+            if plane.distance(&near) < 0.0 {
+                visibility = Visibility::Intersecting;
+            }
+        }
+        visibility
+    }
+}
@@ -0,0 +1,223 @@
+use gltf::{image::Format, mesh::Mode};
+use image::{DynamicImage, ImageBuffer, Luma, LumaA, Rgb, Rgba};
+
+use crate::{
+    mat4x4::{make_identity, Mat4x4},
+    material::{CullMode, Material},
+    mesh::{LoadError, Mesh},
+    scene::{Node, Scene},
+    triangle::Triangle,
+    vec2d::Vec2D,
+    vec3d::Vec3D,
+};
+
+// Load a `.gltf` (with its external or embedded buffers and images) or `.glb`
+// file. Each primitive of each glTF mesh becomes one engine mesh, since a mesh
+// has a single material, and each node lists the engine meshes of its glTF
+// mesh. Texture indices in materials refer to `Scene::textures`, which are the
+// file's images in order. Positions are kept as they are in the file, without
+// recentering
+pub fn load_gltf(filename: &str) -> Result<Scene, LoadError> {
+    let (document, buffers, images) = gltf::import(filename).map_err(|e| match e {
+        gltf::Error::Io(e) => LoadError::Io(e),
+        e => LoadError::Format(e.to_string()),
+    })?;
+
+    let mut scene = Scene::new();
+
+    for image in images {
+        let texture = to_image(image)
+            .ok_or_else(|| LoadError::Format("unsupported image format".to_string()))?;
+        scene.textures.push(texture);
+    }
+
+    // Engine meshes for each glTF mesh
+    let mut primitives = vec![];
+    for mesh in document.meshes() {
+        let mut indices = vec![];
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let positions = reader
+                .read_positions()
+                .ok_or_else(|| LoadError::Format("primitive has no positions".to_string()))?
+                .map(|[x, y, z]| Vec3D::new(x as f64, y as f64, z as f64))
+                .collect::<Vec<_>>();
+            let normals = reader.read_normals().map(|normals| {
+                normals
+                    .map(|[x, y, z]| Vec3D::new(x as f64, y as f64, z as f64))
+                    .collect::<Vec<_>>()
+            });
+            let tex_coords = reader.read_tex_coords(0).map(|t| {
+                t.into_f32()
+                    .map(|[u, v]| Vec2D::new(u as f64, v as f64))
+                    .collect::<Vec<_>>()
+            });
+            let colors = reader.read_colors(0).map(|c| {
+                c.into_rgb_f32()
+                    .map(|c| c.map(|c| c as f64))
+                    .collect::<Vec<_>>()
+            });
+            let vertex_indices = match reader.read_indices() {
+                Some(i) => i.into_u32().map(|i| i as usize).collect(),
+                None => (0..positions.len()).collect::<Vec<_>>(),
+            };
+            if vertex_indices.iter().any(|&i| i >= positions.len()) {
+                return Err(LoadError::Format("vertex index out of range".to_string()));
+            }
+
+            let material = to_material(&primitive.material());
+            let mut tris = vec![];
+            for [a, b, c] in triangle_indices(primitive.mode(), &vertex_indices) {
+                let uv = |i: usize| tex_coords.as_ref().map_or(Vec2D::empty(), |t| t[i]);
+                let mut tri = Triangle::new_uv(
+                    positions[a],
+                    positions[b],
+                    positions[c],
+                    uv(a),
+                    uv(b),
+                    uv(c),
+                );
+                if let Some(normals) = &normals {
+                    tri.n = [normals[a], normals[b], normals[c]];
+                }
+                if let Some(colors) = &colors {
+                    tri.color = [colors[a], colors[b], colors[c]];
+                }
+                tri.cull = material.cull;
+                tris.push(tri);
+            }
+
+            indices.push(scene.meshes.len());
+            scene.meshes.push(Mesh { tris, material });
+        }
+        primitives.push(indices);
+    }
+
+    for node in document.nodes() {
+        // glTF matrices are stored column by column for column vectors, which
+        // is the same layout as the engine's row-major, row vector matrices
+        let transform = node.transform().matrix().map(|row| row.map(|c| c as f64));
+        scene.nodes.push(Node {
+            name: node.name().map(|name| name.to_string()),
+            transform: Mat4x4 { m: transform },
+            meshes: node
+                .mesh()
+                .map_or(vec![], |mesh| primitives[mesh.index()].clone()),
+            children: node.children().map(|child| child.index()).collect(),
+        });
+    }
+
+    // Files without scenes still get their meshes, each drawn once at the origin
+    match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(s) => scene.roots = s.nodes().map(|node| node.index()).collect(),
+        None => {
+            for mesh in primitives {
+                scene.roots.push(scene.nodes.len());
+                scene.nodes.push(Node {
+                    name: None,
+                    transform: make_identity(),
+                    meshes: mesh,
+                    children: vec![],
+                });
+            }
+        }
+    }
+
+    Ok(scene)
+}
+
+// Split a primitive's vertex list into triangles. Points and lines have no
+// triangles
+fn triangle_indices(mode: Mode, indices: &[usize]) -> Vec<[usize; 3]> {
+    match mode {
+        Mode::Triangles => indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect(),
+        // Every other triangle of a strip is flipped to keep the winding
+        Mode::TriangleStrip => indices
+            .windows(3)
+            .enumerate()
+            .map(|(i, t)| {
+                if i % 2 == 0 {
+                    [t[0], t[1], t[2]]
+                } else {
+                    [t[1], t[0], t[2]]
+                }
+            })
+            .collect(),
+        Mode::TriangleFan => indices
+            .windows(2)
+            .skip(1)
+            .map(|t| [indices[0], t[0], t[1]])
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// Approximate a metallic-roughness material with Blinn-Phong. Metals tint their
+// highlights with the base colour and lose their diffuse colour, and rougher
+// surfaces get dimmer, wider highlights. Double sided materials aren't culled
+fn to_material(material: &gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor().map(|c| c as f64);
+    let base = [r, g, b];
+    let metallic = pbr.metallic_factor() as f64;
+    let roughness = (pbr.roughness_factor() as f64).clamp(0.05, 1.0);
+
+    let diffuse = base.map(|c| c * (1.0 - metallic));
+    let specular = base.map(|c| (0.04 + (c - 0.04) * metallic) * (1.0 - roughness * 0.9));
+    let shininess = (2.0 / roughness.powi(4) - 2.0).clamp(1.0, 256.0);
+    let emissive = material.emissive_factor().map(|c| c as f64);
+
+    let mut out = Material::new(diffuse, specular, shininess, emissive);
+    if material.double_sided() {
+        out.cull = CullMode::None;
+    }
+    out.texture = pbr
+        .base_color_texture()
+        .map(|info| info.texture().source().index());
+    out
+}
+
+fn to_image(image: gltf::image::Data) -> Option<DynamicImage> {
+    let (w, h, pixels) = (image.width, image.height, image.pixels);
+    let image = match image.format {
+        Format::R8 => ImageBuffer::<Luma<u8>, _>::from_raw(w, h, pixels)?.into(),
+        Format::R8G8 => ImageBuffer::<LumaA<u8>, _>::from_raw(w, h, pixels)?.into(),
+        Format::R8G8B8 => ImageBuffer::<Rgb<u8>, _>::from_raw(w, h, pixels)?.into(),
+        Format::R8G8B8A8 => ImageBuffer::<Rgba<u8>, _>::from_raw(w, h, pixels)?.into(),
+        Format::R16 => ImageBuffer::<Luma<u16>, _>::from_raw(w, h, to_u16s(&pixels))?.into(),
+        Format::R16G16 => ImageBuffer::<LumaA<u16>, _>::from_raw(w, h, to_u16s(&pixels))?.into(),
+        Format::R16G16B16 => ImageBuffer::<Rgb<u16>, _>::from_raw(w, h, to_u16s(&pixels))?.into(),
+        Format::R16G16B16A16 => {
+            ImageBuffer::<Rgba<u16>, _>::from_raw(w, h, to_u16s(&pixels))?.into()
+        }
+        Format::R32G32B32FLOAT => {
+            ImageBuffer::<Rgb<f32>, _>::from_raw(w, h, to_f32s(&pixels))?.into()
+        }
+        Format::R32G32B32A32FLOAT => {
+            ImageBuffer::<Rgba<f32>, _>::from_raw(w, h, to_f32s(&pixels))?.into()
+        }
+    };
+    Some(image)
+}
+
+// Channels wider than a byte are stored as native-endian bytes
+fn to_u16s(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+        .collect()
+}
+
+fn to_f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
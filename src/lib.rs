@@ -2,12 +2,39 @@ use std::mem;
 
 use image::{DynamicImage, GenericImageView};
 use triangle::Triangle;
-
+use vec2d::Vec2D;
+use vec3d::Vec3D;
+use vertex::{Interpolate, Vertex};
+
+pub mod aabb;
+pub mod animation;
+pub mod bvh;
+pub mod camera_path;
+pub mod capture;
+pub mod clip;
+pub mod fog;
+pub mod frustum;
+pub mod gltf_loader;
+pub mod light;
 pub mod mat4x4;
+pub mod material;
 pub mod mesh;
+pub mod ply;
+pub mod ray;
+pub mod repair;
+pub mod scene;
+pub mod shadow;
+pub mod shapes;
+pub mod simplify;
+pub mod sky;
+pub mod stl;
+pub mod terrain;
+pub mod timestep;
 pub mod triangle;
 pub mod vec2d;
 pub mod vec3d;
+pub mod vertex;
+pub mod watch;
 
 pub fn get_color(lum: f64) -> [u8; 4] {
     let r = (lum * 255.0) as u8;
@@ -16,225 +43,148 @@ pub fn get_color(lum: f64) -> [u8; 4] {
     [r, g, b, 0xff]
 }
 
-pub fn textured_triangle(
+// Light arriving at a surface, with 1.0 being full strength, as a triangle colour
+pub fn get_color_rgb(rgb: [f64; 3]) -> [u8; 4] {
+    let r = (rgb[0].clamp(0.0, 1.0) * 255.0) as u8;
+    let g = (rgb[1].clamp(0.0, 1.0) * 255.0) as u8;
+    let b = (rgb[2].clamp(0.0, 1.0) * 255.0) as u8;
+    [r, g, b, 0xff]
+}
+
+// Fill a screen space triangle, calling `fragment` for every pixel that passes
+// the depth test to get its colour. The triangle's vertex attributes must have
+// been through `Vertex::perspective_divide`; the vertex handed to `fragment`
+// has been interpolated across the triangle and perspective corrected
+pub fn rasterize_triangle(
     frame: &mut [u8],
     canvas_width: i32,
     tri: &Triangle,
-    tex: &DynamicImage,
     depth_buffer: &mut [f64],
+    mut fragment: impl FnMut(&Vertex) -> [u8; 4],
 ) {
-    let mut x1 = tri.p[0].x as i32;
-    let mut y1 = tri.p[0].y as i32;
-    let mut x2 = tri.p[1].x as i32;
-    let mut y2 = tri.p[1].y as i32;
-    let mut x3 = tri.p[2].x as i32;
-    let mut y3 = tri.p[2].y as i32;
-
-    let mut u1 = tri.t[0].u;
-    let mut v1 = tri.t[0].v;
-    let mut u2 = tri.t[1].u;
-    let mut v2 = tri.t[1].v;
-    let mut u3 = tri.t[2].u;
-    let mut v3 = tri.t[2].v;
-
-    let mut w1 = tri.t[0].w;
-    let mut w2 = tri.t[1].w;
-    let mut w3 = tri.t[2].w;
-
-    let tex_width = (tex.width() - 1) as f64;
-    let tex_height = (tex.height() - 1) as f64;
-
     let canvas_height = frame.len() as i32 / 4 / canvas_width;
 
-    if y1 > y2 {
-        mem::swap(&mut y1, &mut y2);
-        mem::swap(&mut x1, &mut x2);
-        mem::swap(&mut u1, &mut u2);
-        mem::swap(&mut v1, &mut v2);
-        mem::swap(&mut w1, &mut w2);
-    }
-    if y1 > y3 {
-        mem::swap(&mut y1, &mut y3);
-        mem::swap(&mut x1, &mut x3);
-        mem::swap(&mut u1, &mut u3);
-        mem::swap(&mut v1, &mut v3);
-        mem::swap(&mut w1, &mut w3);
-    }
-    if y2 > y3 {
-        mem::swap(&mut y2, &mut y3);
-        mem::swap(&mut x2, &mut x3);
-        mem::swap(&mut u2, &mut u3);
-        mem::swap(&mut v2, &mut v3);
-        mem::swap(&mut w2, &mut w3);
-    }
-
-    let mut dy1 = y2 - y1;
-    let mut dx1 = x2 - x1;
-    let mut dv1 = v2 - v1;
-    let mut du1 = u2 - u1;
-    let mut dw1 = w2 - w1;
-
-    let dy2 = y3 - y1;
-    let dx2 = x3 - x1;
-    let dv2 = v3 - v1;
-    let du2 = u3 - u1;
-    let dw2 = w3 - w1;
-
-    // let mut tex_u;
-    // let mut tex_v;
-
-    let mut dax_step = 0.0;
-    let mut dbx_step = 0.0;
-    let mut du1_step = 0.0;
-    let mut dv1_step = 0.0;
-    let mut du2_step = 0.0;
-    let mut dv2_step = 0.0;
-    let mut dw1_step = 0.0;
-    let mut dw2_step = 0.0;
-
-    if dy1 != 0 {
-        dax_step = dx1 as f64 / dy1.abs() as f64;
-    }
-    if dy2 != 0 {
-        dbx_step = dx2 as f64 / dy2.abs() as f64;
-    }
-
-    if dy1 != 0 {
-        du1_step = du1 / dy1.abs() as f64;
-    }
-    if dy1 != 0 {
-        dv1_step = dv1 / dy1.abs() as f64;
-    }
-    if dy1 != 0 {
-        dw1_step = dw1 / dy1.abs() as f64;
-    }
-
-    if dy2 != 0 {
-        du2_step = du2 / dy2.abs() as f64;
-    }
-    if dy2 != 0 {
-        dv2_step = dv2 / dy2.abs() as f64;
-    }
-    if dy2 != 0 {
-        dw2_step = dw2 / dy2.abs() as f64;
-    }
-
-    if dy1 != 0 {
-        for i in y1..=y2 {
-            let mut ax = (x1 as f64 + (i - y1) as f64 * dax_step) as i32;
-            let mut bx = (x1 as f64 + (i - y1) as f64 * dbx_step) as i32;
-
-            let mut tex_su = u1 + (i - y1) as f64 * du1_step;
-            let mut tex_sv = v1 + (i - y1) as f64 * dv1_step;
-            let mut tex_sw = w1 + (i - y1) as f64 * dw1_step;
-
-            let mut tex_eu = u1 + (i - y1) as f64 * du2_step;
-            let mut tex_ev = v1 + (i - y1) as f64 * dv2_step;
-            let mut tex_ew = w1 + (i - y1) as f64 * dw2_step;
-
-            if ax > bx {
-                mem::swap(&mut ax, &mut bx);
-                mem::swap(&mut tex_su, &mut tex_eu);
-                mem::swap(&mut tex_sv, &mut tex_ev);
-                mem::swap(&mut tex_sw, &mut tex_ew);
-            }
-
-            // tex_u = tex_su;
-            // tex_v = tex_sv;
-
-            let t_step = 1.0 / (bx - ax) as f64;
-            let mut t = 0.0;
+    scan_triangle(canvas_width, canvas_height, tri, depth_buffer, |x, y, v| {
+        let rgba = fragment(v);
+        color_position(x, y, canvas_width, canvas_height, frame, &rgba);
+    });
+}
 
-            for j in ax..bx {
-                let tex_u = (1.0 - t) * tex_su + t * tex_eu;
-                let tex_v = (1.0 - t) * tex_sv + t * tex_ev;
-                let tex_w = (1.0 - t) * tex_sw + t * tex_ew;
+// Walk the pixels of a screen space triangle, updating the depth buffer and
+// calling `plot` with each pixel that passes the depth test. This is the part
+// of `rasterize_triangle` that does not need a frame, so it can also be used
+// to render depth only, as for shadow maps
+pub fn scan_triangle(
+    canvas_width: i32,
+    canvas_height: i32,
+    tri: &Triangle,
+    depth_buffer: &mut [f64],
+    mut plot: impl FnMut(i32, i32, &Vertex),
+) {
+    // Sort vertices from top to bottom
+    let mut v = tri.vertices();
+    v.sort_by_key(|v| v.p.y as i32);
+    let [v1, v2, v3] = v;
+
+    let (x1, y1) = (v1.p.x as i32, v1.p.y as i32);
+    let (x2, y2) = (v2.p.x as i32, v2.p.y as i32);
+    let (x3, y3) = (v3.p.x as i32, v3.p.y as i32);
+
+    // Fill one row between a point on a short edge and a point on the long edge
+    let mut span = |i: i32, mut ax: i32, mut bx: i32, mut a: Vertex, mut b: Vertex| {
+        if i < 0 || i >= canvas_height {
+            return;
+        }
+        if ax > bx {
+            mem::swap(&mut ax, &mut bx);
+            mem::swap(&mut a, &mut b);
+        }
 
-                if tex_w > depth_buffer[(i * canvas_width + j) as usize] {
-                    let rgba = tex
-                        .get_pixel(
-                            (tex_u / tex_w * tex_width) as u32,
-                            (tex_v / tex_w * tex_height) as u32,
-                        )
-                        .0;
-                    color_position(j, i, canvas_width, canvas_height, frame, &rgba);
-                    depth_buffer[(i * canvas_width + j) as usize] = tex_w;
+        let t_step = 1.0 / (bx - ax) as f64;
+        let mut t = 0.0;
+
+        for j in ax..bx {
+            // Vertices on the edge of the view volume can round to one pixel
+            // past the canvas
+            if j >= 0 && j < canvas_width {
+                let index = (i * canvas_width + j) as usize;
+                let v = a.lerp(&b, t);
+                if v.p.z < depth_buffer[index] {
+                    plot(j, i, &v.perspective_correct());
+                    depth_buffer[index] = v.p.z;
                 }
-
-                t += t_step;
             }
-        }
-    }
-
-    dy1 = y3 - y2;
-    dx1 = x3 - x2;
-    dv1 = v3 - v2;
-    du1 = u3 - u2;
-    dw1 = w3 - w2;
 
-    if dy1 != 0 {
-        dax_step = dx1 as f64 / dy1.abs() as f64;
-    }
-    if dy2 != 0 {
-        dbx_step = dx2 as f64 / dy2.abs() as f64;
-    }
+            t += t_step;
+        }
+    };
 
-    if dy1 != 0 {
-        du1_step = du1 / dy1.abs() as f64;
-    }
-    if dy1 != 0 {
-        dv1_step = dv1 / dy1.abs() as f64;
-    }
-    if dy1 != 0 {
-        dw1_step = dw1 / dy1.abs() as f64;
+    // Top half, between the edges 1 -> 2 and 1 -> 3
+    if y2 != y1 {
+        for i in y1..=y2 {
+            let ta = (i - y1) as f64 / (y2 - y1) as f64;
+            let tb = (i - y1) as f64 / (y3 - y1) as f64;
+            let ax = (x1 as f64 + (x2 - x1) as f64 * ta) as i32;
+            let bx = (x1 as f64 + (x3 - x1) as f64 * tb) as i32;
+            span(i, ax, bx, v1.lerp(&v2, ta), v1.lerp(&v3, tb));
+        }
     }
 
-    if dy1 != 0 {
+    // Bottom half, between the edges 2 -> 3 and 1 -> 3
+    if y3 != y2 {
         for i in y2..=y3 {
-            let mut ax = (x2 as f64 + (i - y2) as f64 * dax_step) as i32;
-            let mut bx = (x1 as f64 + (i - y1) as f64 * dbx_step) as i32;
-
-            let mut tex_su = u2 + (i - y2) as f64 * du1_step;
-            let mut tex_sv = v2 + (i - y2) as f64 * dv1_step;
-            let mut tex_sw = w2 + (i - y2) as f64 * dw1_step;
-
-            let mut tex_eu = u1 + (i - y1) as f64 * du2_step;
-            let mut tex_ev = v1 + (i - y1) as f64 * dv2_step;
-            let mut tex_ew = w1 + (i - y1) as f64 * dw2_step;
-
-            if ax > bx {
-                mem::swap(&mut ax, &mut bx);
-                mem::swap(&mut tex_su, &mut tex_eu);
-                mem::swap(&mut tex_sv, &mut tex_ev);
-                mem::swap(&mut tex_sw, &mut tex_ew);
-            }
-
-            // tex_u = tex_su;
-            // tex_v = tex_sv;
+            let ta = (i - y2) as f64 / (y3 - y2) as f64;
+            let tb = (i - y1) as f64 / (y3 - y1) as f64;
+            let ax = (x2 as f64 + (x3 - x2) as f64 * ta) as i32;
+            let bx = (x1 as f64 + (x3 - x1) as f64 * tb) as i32;
+            span(i, ax, bx, v2.lerp(&v3, ta), v1.lerp(&v3, tb));
+        }
+    }
+}
 
-            let t_step = 1.0 / (bx - ax) as f64;
-            let mut t = 0.0;
+// Look up a texel, with `uv` running from 0.0 to 1.0 across the texture
+pub fn sample_texture(tex: &DynamicImage, uv: &Vec2D) -> [u8; 4] {
+    let tex_width = (tex.width() - 1) as f64;
+    let tex_height = (tex.height() - 1) as f64;
+    let x = ((uv.u * tex_width) as u32).min(tex.width() - 1);
+    let y = ((uv.v * tex_height) as u32).min(tex.height() - 1);
+    tex.get_pixel(x, y).0
+}
 
-            for j in ax..bx {
-                let tex_u = (1.0 - t) * tex_su + t * tex_eu;
-                let tex_v = (1.0 - t) * tex_sv + t * tex_ev;
-                let tex_w = (1.0 - t) * tex_sw + t * tex_ew;
+// Light a texel with diffuse light, which scales its colour, and specular
+// light, which is added on top
+pub fn shade_texel(texel: &[u8; 4], diffuse: &[f64; 3], specular: &[f64; 3]) -> [u8; 4] {
+    let channel = |i: usize| {
+        let c = texel[i] as f64 / 255.0 * diffuse[i] + specular[i];
+        (c.clamp(0.0, 1.0) * 255.0) as u8
+    };
+    [channel(0), channel(1), channel(2), texel[3]]
+}
 
-                if tex_w > depth_buffer[(i * canvas_width + j) as usize] {
-                    let rgba = tex
-                        .get_pixel(
-                            (tex_u / tex_w * tex_width) as u32,
-                            (tex_v / tex_w * tex_height) as u32,
-                        )
-                        .0;
-                    color_position(j, i, canvas_width, canvas_height, frame, &rgba);
-                    depth_buffer[(i * canvas_width + j) as usize] = tex_w;
-                }
+// Texels are multiplied by the triangle's colour, which holds its lighting
+pub fn textured_triangle(
+    frame: &mut [u8],
+    canvas_width: i32,
+    tri: &Triangle,
+    tex: &DynamicImage,
+    depth_buffer: &mut [f64],
+) {
+    rasterize_triangle(frame, canvas_width, tri, depth_buffer, |v| {
+        modulate(&sample_texture(tex, &v.t), &tri.col)
+    });
+}
 
-                t += t_step;
-            }
-        }
-    }
+// Vertex colours, for models without a texture, multiplied by the triangle's
+// colour like `textured_triangle`
+pub fn colored_triangle(
+    frame: &mut [u8],
+    canvas_width: i32,
+    tri: &Triangle,
+    depth_buffer: &mut [f64],
+) {
+    rasterize_triangle(frame, canvas_width, tri, depth_buffer, |v| {
+        modulate(&get_color_rgb(v.color), &tri.col)
+    });
 }
 
 pub fn draw_triangle(frame: &mut [u8], canvas_width: i32, tri: &Triangle, col: &[u8; 4]) {
@@ -251,6 +201,19 @@ pub fn draw_triangle(frame: &mut [u8], canvas_width: i32, tri: &Triangle, col: &
     );
 }
 
+pub fn draw_line(frame: &mut [u8], canvas_width: i32, p1: &Vec3D, p2: &Vec3D, col: &[u8; 4]) {
+    pixels_primitives::line(frame, canvas_width, p1.x, p1.y, p2.x, p2.y, col);
+}
+
+fn modulate(a: &[u8; 4], b: &[u8; 4]) -> [u8; 4] {
+    [
+        (a[0] as u16 * b[0] as u16 / 255) as u8,
+        (a[1] as u16 * b[1] as u16 / 255) as u8,
+        (a[2] as u16 * b[2] as u16 / 255) as u8,
+        a[3],
+    ]
+}
+
 fn color_position(
     x: i32,
     y: i32,
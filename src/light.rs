@@ -0,0 +1,231 @@
+use crate::{
+    material::Material,
+    vec3d::{dot_product, length, Vec3D},
+};
+
+// How a point or spot light falls off with distance `d`:
+// 1.0 / (constant + linear * d + quadratic * d * d)
+#[derive(Clone, Copy, Debug)]
+pub struct Attenuation {
+    pub constant: f64,
+    pub linear: f64,
+    pub quadratic: f64,
+}
+
+impl Attenuation {
+    pub fn new(constant: f64, linear: f64, quadratic: f64) -> Self {
+        Self {
+            constant,
+            linear,
+            quadratic,
+        }
+    }
+
+    // Falls to roughly 1% of full strength at `range`
+    pub fn from_range(range: f64) -> Self {
+        Self::new(1.0, 4.5 / range, 75.0 / (range * range))
+    }
+
+    pub fn factor(&self, d: f64) -> f64 {
+        1.0 / (self.constant + self.linear * d + self.quadratic * d * d)
+    }
+}
+
+// Directions are the way the light travels, so a directional light shining
+// straight down has a direction of (0, -1, 0). Spot light cone angles are in
+// degrees from the centre of the cone, with light fading out between the inner
+// and outer angles
+#[derive(Clone, Copy, Debug)]
+pub enum LightKind {
+    Directional {
+        direction: Vec3D,
+    },
+    Point {
+        position: Vec3D,
+        attenuation: Attenuation,
+    },
+    Spot {
+        position: Vec3D,
+        direction: Vec3D,
+        inner_angle: f64,
+        outer_angle: f64,
+        attenuation: Attenuation,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: [f64; 3],
+    pub intensity: f64,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3D, color: [f64; 3], intensity: f64) -> Self {
+        Self {
+            kind: LightKind::Directional {
+                direction: direction.normalise(),
+            },
+            color,
+            intensity,
+        }
+    }
+
+    pub fn point(
+        position: Vec3D,
+        attenuation: Attenuation,
+        color: [f64; 3],
+        intensity: f64,
+    ) -> Self {
+        Self {
+            kind: LightKind::Point {
+                position,
+                attenuation,
+            },
+            color,
+            intensity,
+        }
+    }
+
+    pub fn spot(
+        position: Vec3D,
+        direction: Vec3D,
+        inner_angle: f64,
+        outer_angle: f64,
+        attenuation: Attenuation,
+        color: [f64; 3],
+        intensity: f64,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot {
+                position,
+                direction: direction.normalise(),
+                inner_angle,
+                outer_angle,
+                attenuation,
+            },
+            color,
+            intensity,
+        }
+    }
+
+    // Unit vector from `p` towards the light, and how much of the light's
+    // strength reaches `p` before the surface orientation is considered
+    pub fn incident(&self, p: &Vec3D) -> (Vec3D, f64) {
+        match self.kind {
+            LightKind::Directional { direction } => (&direction * -1.0, self.intensity),
+            LightKind::Point {
+                position,
+                attenuation,
+            } => {
+                let to_light = &position - p;
+                let d = length(&to_light);
+                (to_light.normalise(), self.intensity * attenuation.factor(d))
+            }
+            LightKind::Spot {
+                position,
+                direction,
+                inner_angle,
+                outer_angle,
+                attenuation,
+            } => {
+                let to_light = &position - p;
+                let d = length(&to_light);
+                let to_light = to_light.normalise();
+
+                // Smoothly fade between the inner and outer cones
+                let cos_angle = -dot_product(&to_light, &direction);
+                let cos_inner = inner_angle.to_radians().cos();
+                let cos_outer = outer_angle.to_radians().cos();
+                let cone =
+                    ((cos_angle - cos_outer) / (cos_inner - cos_outer).max(1e-6)).clamp(0.0, 1.0);
+
+                (to_light, self.intensity * attenuation.factor(d) * cone)
+            }
+        }
+    }
+}
+
+// Every light in a scene, plus ambient light which reaches all surfaces equally
+#[derive(Clone, Debug)]
+pub struct Lighting {
+    pub ambient: [f64; 3],
+    pub lights: Vec<Light>,
+}
+
+impl Lighting {
+    pub fn new(ambient: [f64; 3]) -> Self {
+        Self {
+            ambient,
+            lights: vec![],
+        }
+    }
+
+    pub fn add(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    // Lambertian diffuse light, plus ambient, arriving at a surface at `p`
+    // facing along the unit normal `n`
+    pub fn diffuse(&self, p: &Vec3D, n: &Vec3D) -> [f64; 3] {
+        let mut total = self.ambient;
+        for light in &self.lights {
+            let (l, strength) = light.incident(p);
+            let dp = dot_product(&l, n).max(0.0) * strength;
+            for (t, c) in total.iter_mut().zip(light.color) {
+                *t += c * dp;
+            }
+        }
+        total
+    }
+
+    // Blinn-Phong lighting of a material at `p` with unit normal `n`, seen from
+    // `eye`. Returns the diffuse light, which scales the surface's texture, and
+    // the specular light, which is added on top of it
+    pub fn blinn_phong(
+        &self,
+        p: &Vec3D,
+        n: &Vec3D,
+        eye: &Vec3D,
+        material: &Material,
+    ) -> ([f64; 3], [f64; 3]) {
+        self.blinn_phong_shadowed(p, n, eye, material, |_, _| 1.0)
+    }
+
+    // As `blinn_phong`, with `shadow` giving how much of the light at an index
+    // in `lights` reaches `p`, from 0.0 to 1.0
+    pub fn blinn_phong_shadowed(
+        &self,
+        p: &Vec3D,
+        n: &Vec3D,
+        eye: &Vec3D,
+        material: &Material,
+        shadow: impl Fn(usize, &Vec3D) -> f64,
+    ) -> ([f64; 3], [f64; 3]) {
+        let mut diffuse: [f64; 3] =
+            std::array::from_fn(|i| material.emissive[i] + self.ambient[i] * material.diffuse[i]);
+        let mut specular = [0.0; 3];
+
+        let to_eye = (eye - p).normalise();
+        for (index, light) in self.lights.iter().enumerate() {
+            let (l, strength) = light.incident(p);
+            let lambert = dot_product(&l, n);
+            if lambert <= 0.0 || strength <= 0.0 {
+                continue;
+            }
+            let strength = strength * shadow(index, p);
+
+            // Highlight peaks where the normal is halfway between the light
+            // and the eye
+            let half = (&l + &to_eye).normalise();
+            let spec = dot_product(n, &half).max(0.0).powf(material.shininess);
+
+            for i in 0..3 {
+                diffuse[i] += light.color[i] * material.diffuse[i] * lambert * strength;
+                specular[i] += light.color[i] * material.specular[i] * spec * strength;
+            }
+        }
+
+        (diffuse, specular)
+    }
+}
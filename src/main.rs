@@ -1,22 +1,55 @@
 use std::{
+    fmt,
+    path::Path,
     time::{Duration, Instant},
     vec,
 };
 
 use engine_3d::{
-    get_color,
+    aabb::Aabb,
+    animation::{CameraAnimation, Interpolation, Playback},
+    bvh::Bvh,
+    camera_path::{CameraPath, CameraPathPlayer, CameraSample},
+    capture::FrameSequence,
+    clip::clip_triangle,
+    draw_line, draw_triangle,
+    fog::Fog,
+    frustum::{CullStats, Frustum, Visibility},
+    get_color_rgb,
+    gltf_loader::load_gltf,
+    light::{Attenuation, Light, LightKind, Lighting},
     mat4x4::{
-        make_projection, make_rotation_x, make_rotation_y, make_rotation_z, make_translation,
-        multiply_matrix, multiply_vector, point_at, quick_inverse, Mat4x4,
+        inverse, make_look_at, make_orthographic, make_projection, make_rotation_x,
+        make_rotation_y, make_rotation_z, make_translation, multiply_direction, multiply_matrix,
+        multiply_vector, Handedness, Mat4x4,
     },
-    mesh::Mesh,
-    textured_triangle,
+    material::{CullMode, Material, Shading},
+    mesh::{LoadError, LoadOptions, Mesh},
+    ply::load_ply,
+    rasterize_triangle,
+    ray::{screen_to_ray, Hit},
+    repair::{analyze, repair},
+    sample_texture, shade_texel,
+    shadow::ShadowMap,
+    simplify::LodMesh,
+    sky::{Cubemap, Sky},
+    stl::load_stl,
+    terrain::{Heightmap, Terrain},
+    timestep::{FixedTimestep, FpsCounter, FrameLimiter},
     triangle::Triangle,
-    vec3d::{clip_against_plane, cross_product, dot_product, Vec3D},
+    vec3d::{cross_product, dot_product, Vec3D},
+    vertex::Interpolate,
+    watch::WatchedFile,
+};
+use image::{DynamicImage, ImageError, ImageReader};
+use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
+use winit::{
+    dpi::PhysicalSize,
+    event::{Event, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    keyboard::KeyCode,
+    window::{Window, WindowBuilder},
 };
-use image::{DynamicImage, ImageReader};
-use pixels::{Pixels, SurfaceTexture};
-use winit::{dpi::PhysicalSize, event_loop::EventLoop, keyboard::KeyCode, window::WindowBuilder};
 use winit_input_helper::WinitInputHelper;
 
 const WIDTH: i32 = 256;
@@ -25,11 +58,86 @@ const SCALE: i32 = 4;
 
 const SPEED: f64 = 16.0;
 
+// Seconds of the flythrough skipped per second while scrubbing
+const FLYTHROUGH_DURATION: f64 = 40.0;
+const SCRUB_SPEED: f64 = 4.0;
+
+// Simulation updates per second, independent of the frame rate
+const STEPS_PER_SECOND: f64 = 60.0;
+
+// Frame rate limit toggled with V, on top of waiting for vsync if enabled.
+// Vsync starts on and is toggled with Ctrl+V
+const FRAME_CAP: f64 = 30.0;
+
+const NEAR: f64 = 0.1;
+const FAR: f64 = 1000.0;
+
+// Radius of the sphere around the camera used for collision
+const CAMERA_RADIUS: f64 = 0.5;
+
+const SHADOW_MAP_SIZE: usize = 1024;
+// Shortest time between redrawing the shadow map as the terrain's detail
+// changes, since drawing it takes about as long as a few frames
+const SHADOW_REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+// Terrain built from a heightmap image given on the command line. Chunks are
+// drawn with less detail past `TERRAIN_LOD_DISTANCE`, and while walking the
+// camera stays `EYE_HEIGHT` above the ground
+const TERRAIN_CELL_SIZE: f64 = 1.0;
+const TERRAIN_HEIGHT: f64 = 32.0;
+const TERRAIN_CHUNK_CELLS: usize = 32;
+const TERRAIN_LEVELS: usize = 4;
+const TERRAIN_LOD_DISTANCE: f64 = 48.0;
+const EYE_HEIGHT: f64 = 2.0;
+
+// Simplified copies of the mesh, each with `LOD_RATIO` times the triangles of
+// the one before. The first is used from `LOD_DISTANCE` times the mesh's
+// bounding radius away, and each next one from twice as far
+const LOD_LEVELS: usize = 4;
+const LOD_RATIO: f64 = 0.5;
+const LOD_DISTANCE: f64 = 4.0;
+
+// Corners closer than this are treated as the same vertex when checking and
+// repairing the mesh, and repaired normals are smoothed across edges shallower
+// than `CREASE_ANGLE` degrees, leaving sharper edges creased
+const REPAIR_EPSILON: f64 = 1e-5;
+const CREASE_ANGLE: f64 = 45.0;
+
+const MESH_FILE: &str = "models/spyro_level.obj";
+const TEXTURE_FILE: &str = "textures/spyro_high.png";
+
+// How often the asset files are checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+const SCREENSHOT_DIR: &str = "screenshots";
+const RECORDING_DIR: &str = "recordings";
+
+const CAMERA_PATH_FILE: &str = "camera_path.txt";
+
+const SKY_COLOR: [u8; 4] = [107, 229, 252, 0xff];
+// Faces of the cubemap sky, in the order `Cubemap` expects
+const SKYBOX_FILES: [&str; 6] = [
+    "textures/skybox/px.png",
+    "textures/skybox/nx.png",
+    "textures/skybox/py.png",
+    "textures/skybox/ny.png",
+    "textures/skybox/pz.png",
+    "textures/skybox/nz.png",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum View {
+    Perspective,
+    Top,
+    Front,
+    Side,
+}
+
 struct Engine3D {
-    elapsed_time: Duration,
     theta: f64,
 
     mesh_cube: Mesh,
+    bvh: Bvh,
     mat_proj: Mat4x4,
 
     camera: Vec3D,
@@ -37,33 +145,624 @@ struct Engine3D {
 
     yaw: f64,
 
-    spr_tex: DynamicImage,
+    // Camera as of the previous step, for blending between steps
+    prev_camera: Vec3D,
+    prev_yaw: f64,
+
+    // Camera path around the level, followed while `flythrough_active` is set.
+    // The camera looks at `look_target` instead of along `yaw` while following
+    flythrough: CameraAnimation,
+    playback: Playback,
+    flythrough_active: bool,
+    look_target: Option<Vec3D>,
+    prev_look_target: Option<Vec3D>,
+
+    view: View,
+    // Half the visible height of the orthographic views, in world units
+    ortho_size: f64,
+
+    // Highlight the triangle under the cursor
+    picking: bool,
+    picked: Option<Hit>,
+    picked_tris: Vec<Triangle>,
+
+    // Stop the camera from moving into the mesh
+    collision: bool,
+
+    // Set when the model is a heightmap, in which case `mesh_cube` holds the
+    // terrain's chunks at their current detail
+    terrain: Option<Terrain>,
+    walking: bool,
+
+    // Swap `mesh_cube` for a simplified level further from the camera. Each
+    // level has its own BVH, and `lod_level` is the one in use
+    lod_enabled: bool,
+    lod: Option<LodMesh>,
+    lod_bvhs: Vec<Bvh>,
+    lod_level: usize,
+
+    // Draw the BVH boxes at `bvh_depth` levels below the root
+    show_bvh: bool,
+    bvh_depth: usize,
+    debug_lines: Vec<(Vec3D, Vec3D)>,
+
+    lighting: Lighting,
+    // Lights, eye position and matrices used for the current frame
+    frame_lighting: Lighting,
+    eye: Vec3D,
+    frame_proj: Mat4x4,
+    frame_view_proj: Mat4x4,
+
+    sky: Sky,
+    fog: Option<Fog>,
+    // Add a spot light shining from the camera
+    flashlight: bool,
+
+    // Shadows cast by the directional light at `shadow_light` in `lighting`
+    shadow_map: ShadowMap,
+    shadow_light: Option<usize>,
+    shadows: bool,
+    // Set when the mesh changed since the shadow map was drawn
+    shadow_stale: bool,
+    since_shadow_map: Duration,
+
+    // Skip objects and BVH chunks outside the view frustum
+    culling: bool,
+    cull_stats: CullStats,
+
+    // The default level's texture, or the base colour texture of a glTF model
+    spr_tex: Option<DynamicImage>,
+
+    // Reload the mesh and texture when they change on disk
+    mesh_path: String,
+    mesh_file: WatchedFile,
+    tex_file: Option<WatchedFile>,
+    since_reload_check: Duration,
+    // Latest error loading each file, shown until the file loads again
+    mesh_error: Option<String>,
+    tex_error: Option<String>,
+
+    // Save the next frame as a screenshot, or every frame while recording,
+    // with a grayscale depth image alongside when `capture_depth` is set
+    screenshots: FrameSequence,
+    screenshot_requested: bool,
+    recording: Option<FrameSequence>,
+    capture_depth: bool,
+
+    // Record the camera each step to `CAMERA_PATH_FILE`, or replay it from
+    // there one recorded step per frame
+    path_recording: Option<CameraPath>,
+    path_player: Option<CameraPathPlayer>,
+
+    // Latest message about capturing or camera paths, shown in the title
+    status: Option<String>,
 }
 
 impl Engine3D {
-    fn new() -> Self {
-        let mesh_cube = Mesh::from_file("models/spyro_level.obj", true);
-        let spr_tex = ImageReader::open("textures/spyro_high.png")
-            .unwrap()
-            .decode()
-            .unwrap();
+    // Show the model at `mesh_path`, or the textured Spyro level if none is
+    // given. Images are loaded as heightmap terrain. Files that fail to load
+    // are reported the same way as when reloading, and the viewer starts
+    // without them until they are fixed on disk
+    fn new(mesh_path: Option<String>) -> Self {
+        let default_level = mesh_path.is_none();
+        let mesh_path = mesh_path.unwrap_or_else(|| MESH_FILE.to_string());
 
-        let mat_proj = make_projection(90.0, HEIGHT as f64 / WIDTH as f64, 0.1, 1000.0);
+        let (mut spr_tex, mut tex_error) = (None, None);
+        if default_level {
+            match load_texture(TEXTURE_FILE) {
+                Ok(tex) => spr_tex = Some(tex),
+                Err(e) => tex_error = Some(load_error(TEXTURE_FILE, e)),
+            }
+        }
 
-        Self {
-            elapsed_time: Duration::new(0, 0),
+        let (mut mesh_cube, texture, terrain, mesh_error) =
+            match load_model(&mesh_path, default_level) {
+                Ok((mesh, texture, terrain)) => (mesh, texture, terrain, None),
+                Err(e) => (
+                    Mesh::new(vec![]),
+                    None,
+                    None,
+                    Some(load_error(&mesh_path, e)),
+                ),
+            };
+        if default_level {
+            mesh_cube.material = Material::new([1.0; 3], [0.15; 3], 16.0, [0.0; 3]);
+        }
+        let bvh = Bvh::new(&mesh_cube);
+        let tex_file = default_level.then(|| WatchedFile::new(TEXTURE_FILE));
+        let spr_tex = spr_tex.or(texture);
+
+        let mut lighting = Lighting::new([0.1, 0.1, 0.1]);
+        lighting.add(Light::directional(
+            Vec3D::new(0.0, -1.0, 1.0),
+            [1.0, 1.0, 1.0],
+            0.9,
+        ));
+
+        let mat_proj = make_projection(90.0, HEIGHT as f64 / WIDTH as f64, NEAR, FAR);
+
+        let mut engine = Self {
             theta: 0.0,
             mesh_cube,
+            bvh,
             mat_proj,
             camera: Vec3D::empty(),
             look_dir: Vec3D::empty(),
             yaw: 0.0,
+            prev_camera: Vec3D::empty(),
+            prev_yaw: 0.0,
+            flythrough: CameraAnimation::new(Interpolation::Cubic),
+            playback: Playback::new(0.0),
+            flythrough_active: false,
+            look_target: None,
+            prev_look_target: None,
+            view: View::Perspective,
+            ortho_size: 100.0,
+            picking: false,
+            picked: None,
+            picked_tris: vec![],
+            collision: false,
+            terrain,
+            walking: false,
+            lod_enabled: false,
+            lod: None,
+            lod_bvhs: vec![],
+            lod_level: 0,
+            show_bvh: false,
+            bvh_depth: 4,
+            debug_lines: vec![],
+            frame_lighting: lighting.clone(),
+            lighting,
+            eye: Vec3D::empty(),
+            frame_proj: mat_proj,
+            frame_view_proj: mat_proj,
+            sky: Sky::Color(SKY_COLOR),
+            fog: Some(Fog::Linear {
+                start: FAR * 0.5,
+                end: FAR,
+            }),
+            flashlight: false,
+            culling: true,
+            cull_stats: CullStats::default(),
+            shadow_map: ShadowMap::new(SHADOW_MAP_SIZE),
+            shadow_light: None,
+            shadows: true,
+            shadow_stale: false,
+            since_shadow_map: Duration::ZERO,
+            tex_file,
             spr_tex,
+            mesh_file: WatchedFile::new(&mesh_path),
+            mesh_path,
+            since_reload_check: Duration::ZERO,
+            mesh_error,
+            tex_error,
+            screenshots: FrameSequence::new(SCREENSHOT_DIR, "screenshot", false),
+            screenshot_requested: false,
+            recording: None,
+            capture_depth: false,
+            path_recording: None,
+            path_player: None,
+            status: None,
+        };
+        engine.render_shadow_map();
+        engine.make_flythrough();
+        engine
+    }
+
+    // Circle the level, looking across it while rising and falling. Built from
+    // the mesh bounds, so it suits whatever mesh is loaded
+    fn make_flythrough(&mut self) {
+        let mat_world = self.world_matrix();
+        let mut bounds = Aabb::empty();
+        let mesh_bounds = self.mesh_cube.bounds();
+        // Nothing to circle around an empty mesh
+        if mesh_bounds.is_empty() {
+            self.flythrough = CameraAnimation::new(Interpolation::Cubic);
+            self.playback = Playback::new(0.0);
+            return;
+        }
+        for corner in mesh_bounds.corners() {
+            bounds.expand(&multiply_vector(&mat_world, &corner));
+        }
+        let center = bounds.center();
+        let size = bounds.size();
+        let radius = size.x.max(size.z) * 0.35;
+
+        let mut flythrough = CameraAnimation::new(Interpolation::Cubic);
+        let keys = 8;
+        for i in 0..=keys {
+            let time = i as f64 * FLYTHROUGH_DURATION / keys as f64;
+            let angle = i as f64 / keys as f64 * std::f64::consts::TAU;
+            let height = if i % 2 == 0 { 0.25 } else { 0.1 };
+            flythrough.position.add(
+                time,
+                Vec3D::new(
+                    center.x + radius * angle.cos(),
+                    center.y + size.y * height,
+                    center.z + radius * angle.sin(),
+                ),
+            );
+            // Look at a point part way around the circle ahead of the camera
+            let ahead = angle + 1.5;
+            flythrough.target.add(
+                time,
+                Vec3D::new(
+                    center.x + radius * 0.3 * ahead.cos(),
+                    center.y,
+                    center.z + radius * 0.3 * ahead.sin(),
+                ),
+            );
         }
+
+        self.playback.duration = flythrough.duration();
+        self.playback.seek(self.playback.time);
+        self.flythrough = flythrough;
     }
 
-    fn update(&mut self, input: &WinitInputHelper) -> Vec<Triangle> {
-        let elapsed_time = self.elapsed_time.as_secs_f64();
+    fn world_matrix(&self) -> Mat4x4 {
+        let mat_rot_z = make_rotation_z(self.theta * 0.5);
+        let mat_rot_x = make_rotation_x(self.theta);
+
+        let mat_trans = make_translation(0.0, 0.0, 5.0);
+
+        let mat_world = multiply_matrix(&mat_rot_z, &mat_rot_x);
+        multiply_matrix(&mat_world, &mat_trans)
+    }
+
+    // Render the shadow map for the first directional light. The scene is
+    // static, so this only needs doing when the mesh or light changes
+    fn render_shadow_map(&mut self) {
+        self.shadow_stale = false;
+        self.since_shadow_map = Duration::ZERO;
+        self.shadow_light = self
+            .lighting
+            .lights
+            .iter()
+            .position(|l| matches!(l.kind, LightKind::Directional { .. }));
+        let Some(index) = self.shadow_light else {
+            return;
+        };
+        let LightKind::Directional { direction } = self.lighting.lights[index].kind else {
+            return;
+        };
+
+        let mat_world = self.world_matrix();
+        let mut bounds = Aabb::empty();
+        let tris = self
+            .mesh_cube
+            .tris
+            .iter()
+            .map(|tri| {
+                let mut tri = *tri;
+                for p in &mut tri.p {
+                    *p = multiply_vector(&mat_world, p);
+                    bounds.expand(p);
+                }
+                tri
+            })
+            .collect::<Vec<_>>();
+
+        self.shadow_map.render(&tris, &direction, &bounds);
+    }
+
+    // Reload whichever asset files changed on disk. A file that fails to load
+    // leaves the previous version in place and the error is reported instead
+    fn reload_assets(&mut self, dt: Duration) {
+        self.since_reload_check += dt;
+        if self.since_reload_check < RELOAD_INTERVAL {
+            return;
+        }
+        self.since_reload_check = Duration::ZERO;
+
+        if self.mesh_file.changed() {
+            match load_model(&self.mesh_path, self.tex_file.is_some()) {
+                Ok((mut mesh, texture, terrain)) => {
+                    mesh.material = self.mesh_cube.material;
+                    // The default level's texture is watched on its own
+                    if self.tex_file.is_none() {
+                        self.spr_tex = texture;
+                    }
+                    self.terrain = terrain;
+                    self.lod = None;
+                    self.lod_bvhs.clear();
+                    self.lod_level = 0;
+                    self.bvh = Bvh::new(&mesh);
+                    self.mesh_cube = mesh;
+                    self.picked = None;
+                    self.render_shadow_map();
+                    self.make_flythrough();
+                    self.mesh_error = None;
+                }
+                Err(e) => self.mesh_error = Some(load_error(&self.mesh_path, e)),
+            }
+        }
+
+        if self.tex_file.as_mut().is_some_and(|f| f.changed()) {
+            match load_texture(TEXTURE_FILE) {
+                Ok(tex) => {
+                    self.spr_tex = Some(tex);
+                    self.tex_error = None;
+                }
+                Err(e) => self.tex_error = Some(load_error(TEXTURE_FILE, e)),
+            }
+        }
+    }
+
+    // How much of a light reaches a world space point
+    fn shadow(&self, index: usize, p: &Vec3D) -> f64 {
+        if self.shadows && self.shadow_light == Some(index) {
+            self.shadow_map.visibility(p)
+        } else {
+            1.0
+        }
+    }
+
+    // Keys that toggle settings, handled once per batch of input events
+    fn handle_keys(&mut self, input: &WinitInputHelper) {
+        if input.key_pressed(KeyCode::Digit1) {
+            self.view = View::Perspective;
+        }
+        if input.key_pressed(KeyCode::Digit2) {
+            self.view = View::Top;
+        }
+        if input.key_pressed(KeyCode::Digit3) {
+            self.view = View::Front;
+        }
+        if input.key_pressed(KeyCode::Digit4) {
+            self.view = View::Side;
+        }
+
+        if input.key_pressed(KeyCode::KeyP) {
+            self.picking = !self.picking;
+        }
+        if input.key_pressed(KeyCode::KeyC) {
+            self.collision = !self.collision;
+        }
+        if input.key_pressed(KeyCode::KeyX) {
+            self.walking = !self.walking;
+        }
+        if input.key_pressed(KeyCode::KeyI) {
+            let report = analyze(&self.mesh_cube, REPAIR_EPSILON);
+            println!("{}: {}", self.mesh_path, report);
+            self.status = Some(report.to_string());
+        }
+        if input.key_pressed(KeyCode::KeyZ) {
+            self.repair_mesh();
+        }
+        if input.key_pressed(KeyCode::KeyU) {
+            self.lod_enabled = !self.lod_enabled;
+            if !self.lod_enabled {
+                self.set_lod_level(0);
+            }
+        }
+        if input.key_pressed(KeyCode::KeyM) {
+            let material = &mut self.mesh_cube.material;
+            material.shading = match material.shading {
+                Shading::Vertex => Shading::Pixel,
+                Shading::Pixel => Shading::Vertex,
+            };
+        }
+        if input.key_pressed(KeyCode::Tab) {
+            // Every triangle takes the new mode, replacing the ones their
+            // materials gave them until the model is loaded again
+            let material = &mut self.mesh_cube.material;
+            material.cull = match material.cull {
+                CullMode::Back => CullMode::None,
+                CullMode::None => CullMode::Front,
+                CullMode::Front => CullMode::Back,
+            };
+            for tri in &mut self.mesh_cube.tris {
+                tri.cull = material.cull;
+            }
+            self.status = Some(
+                match material.cull {
+                    CullMode::Back => "Culling back faces",
+                    CullMode::Front => "Culling front faces",
+                    CullMode::None => "Drawing both sides of faces",
+                }
+                .to_string(),
+            );
+        }
+        if input.key_pressed(KeyCode::KeyH) {
+            self.shadows = !self.shadows;
+        }
+        if input.key_pressed(KeyCode::KeyJ) {
+            self.shadow_map.pcf = if self.shadow_map.pcf == 0 { 1 } else { 0 };
+        }
+        if input.key_pressed(KeyCode::KeyG) {
+            self.fog = match self.fog {
+                None => Some(Fog::Linear {
+                    start: FAR * 0.5,
+                    end: FAR,
+                }),
+                Some(Fog::Linear { .. }) => Some(Fog::Exponential { density: 0.004 }),
+                Some(Fog::Exponential { .. }) => Some(Fog::ExponentialSquared { density: 0.004 }),
+                Some(Fog::ExponentialSquared { .. }) => None,
+            };
+        }
+        if input.key_pressed(KeyCode::KeyK) {
+            self.sky = match self.sky {
+                Sky::Color(_) => Sky::Gradient {
+                    zenith: [40, 110, 220, 0xff],
+                    horizon: SKY_COLOR,
+                    ground: [90, 100, 110, 0xff],
+                },
+                // Skip to the plain colour if the cubemap can't be loaded
+                Sky::Gradient { .. } => match Cubemap::from_files(SKYBOX_FILES) {
+                    Ok(cubemap) => Sky::Cubemap(Box::new(cubemap)),
+                    Err(e) => {
+                        eprintln!("Error loading sky: {}", e);
+                        Sky::Color(SKY_COLOR)
+                    }
+                },
+                Sky::Cubemap(_) => Sky::Color(SKY_COLOR),
+            };
+        }
+        if input.key_pressed(KeyCode::KeyL) {
+            self.flashlight = !self.flashlight;
+        }
+        if input.key_pressed(KeyCode::KeyT) {
+            self.flythrough_active = !self.flythrough_active;
+            if self.flythrough_active {
+                self.playback.play();
+            }
+        }
+        if input.key_pressed(KeyCode::KeyY) {
+            self.playback.toggle();
+        }
+        if input.key_pressed(KeyCode::KeyE) {
+            match self.path_recording.take() {
+                None => {
+                    self.path_recording = Some(CameraPath::new());
+                    self.status = Some("Recording camera path".to_string());
+                }
+                Some(path) => {
+                    self.status = Some(match path.save(CAMERA_PATH_FILE) {
+                        Ok(()) => format!(
+                            "Saved {:.1}s camera path to {}",
+                            path.duration(),
+                            CAMERA_PATH_FILE
+                        ),
+                        Err(e) => {
+                            eprintln!("Error saving camera path: {}", e);
+                            format!("Error saving camera path: {}", e)
+                        }
+                    });
+                }
+            }
+        }
+        if input.key_pressed(KeyCode::KeyQ) {
+            // Replaying would replace the path being recorded, so the recording
+            // has to be saved first
+            if self.path_recording.is_some() {
+                self.status = Some("Press E to save the camera path before replaying".to_string());
+            } else if self.path_player.take().is_none() {
+                match CameraPath::load(CAMERA_PATH_FILE) {
+                    Ok(path) => {
+                        self.path_player = Some(CameraPathPlayer::new(path));
+                        self.status = Some(format!("Replaying {}", CAMERA_PATH_FILE));
+                    }
+                    Err(e) => {
+                        eprintln!("Error loading camera path: {}", e);
+                        self.status = Some(format!("Error loading camera path: {}", e));
+                    }
+                }
+            } else {
+                self.status = None;
+            }
+        }
+        if input.key_pressed(KeyCode::KeyO) {
+            self.screenshot_requested = true;
+        }
+        if input.key_pressed(KeyCode::KeyR) {
+            self.recording = match self.recording {
+                None => Some(FrameSequence::new(
+                    RECORDING_DIR,
+                    "frame",
+                    self.capture_depth,
+                )),
+                Some(_) => {
+                    self.status = None;
+                    None
+                }
+            };
+        }
+        if input.key_pressed(KeyCode::KeyN) {
+            self.capture_depth = !self.capture_depth;
+        }
+        if input.key_pressed(KeyCode::KeyF) {
+            self.culling = !self.culling;
+        }
+        if input.key_pressed(KeyCode::KeyB) {
+            self.show_bvh = !self.show_bvh;
+        }
+        if input.key_pressed(KeyCode::BracketLeft) {
+            self.bvh_depth = self.bvh_depth.saturating_sub(1);
+        }
+        if input.key_pressed(KeyCode::BracketRight) {
+            self.bvh_depth += 1;
+        }
+    }
+
+    // Advance the simulation by one fixed step
+    fn step(&mut self, input: &WinitInputHelper, dt: Duration) {
+        self.reload_assets(dt);
+        self.since_shadow_map += dt;
+        if self.shadow_stale && self.since_shadow_map >= SHADOW_REFRESH_INTERVAL {
+            self.render_shadow_map();
+        }
+
+        self.prev_camera = self.camera;
+        self.prev_yaw = self.yaw;
+        self.prev_look_target = self.look_target;
+
+        if let Some(player) = &mut self.path_player {
+            match player.next_sample() {
+                Some(sample) => {
+                    self.camera = sample.position;
+                    self.yaw = sample.yaw;
+                    self.look_dir = sample.look_dir;
+                    self.look_target = Some(&sample.position + &sample.look_dir);
+                    return;
+                }
+                None => {
+                    self.path_player = None;
+                    self.status = Some("Finished replaying camera path".to_string());
+                }
+            }
+        }
+
+        self.move_camera(input, dt.as_secs_f64());
+
+        if let Some(path) = &mut self.path_recording {
+            let look_dir = match self.look_target {
+                Some(target) => (&target - &self.camera).normalise(),
+                None => self.look_dir,
+            };
+            path.push(CameraSample {
+                dt: dt.as_secs_f64(),
+                position: self.camera,
+                yaw: self.yaw,
+                look_dir,
+            });
+        }
+    }
+
+    // Length of the next step of the camera path being replayed
+    fn replay_dt(&self) -> Option<Duration> {
+        let player = self.path_player.as_ref()?;
+        let sample = player.path.samples.get(player.position())?;
+        Some(Duration::from_secs_f64(sample.dt))
+    }
+
+    // Move the camera for a step, following the flythrough or the keyboard
+    fn move_camera(&mut self, input: &WinitInputHelper, elapsed_time: f64) {
+        if self.flythrough_active {
+            if input.key_held(KeyCode::Comma) {
+                self.playback
+                    .seek(self.playback.time - SCRUB_SPEED * elapsed_time);
+            }
+            if input.key_held(KeyCode::Period) {
+                self.playback
+                    .seek(self.playback.time + SCRUB_SPEED * elapsed_time);
+            }
+            self.playback.advance(elapsed_time);
+
+            if let Some((position, target)) = self.flythrough.sample(self.playback.time) {
+                self.camera = position;
+                self.look_target = Some(target);
+
+                // Keep facing the same way when the flythrough is turned off
+                let dir = &target - &position;
+                self.yaw = (-dir.x).atan2(dir.z);
+                self.look_dir =
+                    multiply_vector(&make_rotation_y(self.yaw), &Vec3D::new(0.0, 0.0, 1.0));
+                return;
+            }
+        }
+        self.look_target = None;
+
+        let start_position = self.camera;
 
         if input.key_held(KeyCode::ArrowUp) || input.key_held(KeyCode::Space) {
             self.camera.y += SPEED * elapsed_time;
@@ -96,39 +795,296 @@ impl Engine3D {
             self.yaw += 2.0 * elapsed_time;
         }
 
-        // self.theta += 1.0 * self.elapsed_time.as_secs_f64();
-        let mat_rot_z = make_rotation_z(self.theta * 0.5);
-        let mat_rot_x = make_rotation_x(self.theta);
+        if input.key_held(KeyCode::Minus) {
+            self.ortho_size *= 1.0 + elapsed_time;
+        }
+        if input.key_held(KeyCode::Equal) {
+            self.ortho_size /= 1.0 + elapsed_time;
+        }
 
-        let mat_trans = make_translation(0.0, 0.0, 5.0);
+        // self.theta += 1.0 * elapsed_time;
+        let mat_world_inv = inverse(&self.world_matrix());
+
+        // Undo this step's movement if it would put the camera inside the mesh.
+        // Movement is never blocked if the camera was already inside
+        if self.collision {
+            if let Some(mat_world_inv) = &mat_world_inv {
+                let collides = |p: &Vec3D| {
+                    let local = multiply_vector(mat_world_inv, p);
+                    self.bvh
+                        .intersects_sphere(&self.mesh_cube, &local, CAMERA_RADIUS)
+                };
+                if collides(&self.camera) && !collides(&start_position) {
+                    self.camera = start_position;
+                }
+            }
+        }
 
-        let mut mat_world = multiply_matrix(&mat_rot_z, &mat_rot_x);
-        mat_world = multiply_matrix(&mat_world, &mat_trans);
+        // Stand on the terrain, wherever the camera is over it
+        if self.walking {
+            if let (Some(terrain), Some(mat_world_inv)) = (&self.terrain, &mat_world_inv) {
+                let local = multiply_vector(mat_world_inv, &self.camera);
+                if let Some(ground) = terrain.height_at(local.x, local.z) {
+                    let feet = Vec3D::new(local.x, ground + EYE_HEIGHT, local.z);
+                    self.camera = multiply_vector(&self.world_matrix(), &feet);
+                }
+            }
+        }
 
-        let up = Vec3D::new(0.0, 1.0, 0.0);
-        let mut target = Vec3D::new(0.0, 0.0, 1.0);
+        let target = Vec3D::new(0.0, 0.0, 1.0);
         let mat_camera_rot = make_rotation_y(self.yaw);
         self.look_dir = multiply_vector(&mat_camera_rot, &target);
-        target = &self.camera + &self.look_dir;
+    }
+
+    // Fix what can be fixed in the mesh in place. The simplified levels are
+    // made again from the repaired mesh when next needed
+    fn repair_mesh(&mut self) {
+        self.set_lod_level(0);
+        let (before, after) = repair(&mut self.mesh_cube, REPAIR_EPSILON, CREASE_ANGLE);
+        println!(
+            "Repaired {}\n  before: {}\n  after: {}",
+            self.mesh_path, before, after
+        );
+        self.status = Some(format!("Repaired: {}", after));
+
+        self.bvh = Bvh::new(&self.mesh_cube);
+        self.lod = None;
+        self.lod_bvhs.clear();
+        self.picked = None;
+        self.render_shadow_map();
+    }
+
+    // Draw the level of detail that suits the camera's distance from the mesh,
+    // making the levels the first time they are needed. Terrain has its own
+    // levels of detail instead
+    fn update_lod(&mut self, camera: &Vec3D) {
+        if !self.lod_enabled || self.terrain.is_some() {
+            return;
+        }
+        // Distances are measured in units of `LOD_DISTANCE` bounding radii, so
+        // the first simplified level starts at 1.0
+        if self.lod.is_none() {
+            let lod = LodMesh::new(self.mesh_cube.clone(), LOD_LEVELS, LOD_RATIO, 1.0);
+            self.lod_bvhs = lod.levels.iter().map(|l| Bvh::new(&l.mesh)).collect();
+            self.lod = Some(lod);
+        }
+
+        let mat_world = self.world_matrix();
+        let mut bounds = Aabb::empty();
+        for corner in self.lod_bvhs[0].bounds().corners() {
+            bounds.expand(&multiply_vector(&mat_world, &corner));
+        }
+        let (_, radius) = bounds.bounding_sphere();
+        let distance = bounds.distance(camera) / (radius * LOD_DISTANCE);
+        if let Some(lod) = &self.lod {
+            self.set_lod_level(lod.level_for_distance(distance));
+        }
+    }
+
+    fn set_lod_level(&mut self, level: usize) {
+        let Some(lod) = &self.lod else {
+            return;
+        };
+        if level == self.lod_level {
+            return;
+        }
+        self.mesh_cube.tris = lod.levels[level].mesh.tris.clone();
+        self.bvh = self.lod_bvhs[level].clone();
+        self.lod_level = level;
+        self.picked = None;
+        self.shadow_stale = true;
+    }
+
+    // Pick the terrain's detail for the camera, and gather the mesh and BVH
+    // again if any chunk changed. Only the changed chunks are rebuilt, along
+    // with their own BVHs. The shadow map is redrawn from the new triangles at
+    // the next step that `SHADOW_REFRESH_INTERVAL` allows
+    fn update_terrain(&mut self, camera: &Vec3D) {
+        let Some(mat_world_inv) = inverse(&self.world_matrix()) else {
+            return;
+        };
+        let Some(terrain) = &mut self.terrain else {
+            return;
+        };
+        if terrain.update(&multiply_vector(&mat_world_inv, camera)) == 0 {
+            return;
+        }
+
+        self.mesh_cube.tris = terrain.mesh().tris;
+        self.bvh = terrain.bvh();
+        self.picked = None;
+        self.shadow_stale = true;
+    }
+
+    // Build the frame's triangles, with the camera blended `alpha` of the way
+    // from where it was at the previous step to where it is now
+    fn update(&mut self, alpha: f64, cursor: Option<(usize, usize)>) -> Vec<Triangle> {
+        let camera = self.prev_camera.lerp(&self.camera, alpha);
+        let yaw = self.prev_yaw.lerp(&self.yaw, alpha);
+
+        self.update_terrain(&camera);
+        self.update_lod(&camera);
+
+        let mat_world = self.world_matrix();
+        let mat_world_inv = inverse(&mat_world);
+
+        let look_dir = match (self.prev_look_target, self.look_target) {
+            (Some(a), Some(b)) => (&a.lerp(&b, alpha) - &camera).normalise(),
+            _ => {
+                let target = Vec3D::new(0.0, 0.0, 1.0);
+                let mat_camera_rot = make_rotation_y(yaw);
+                multiply_vector(&mat_camera_rot, &target)
+            }
+        };
 
-        let mat_camera = point_at(&self.camera, &target, &up);
+        // Orthographic views look along a fixed world axis from the camera
+        let (view_dir, up) = match self.view {
+            View::Perspective => (look_dir, Vec3D::new(0.0, 1.0, 0.0)),
+            View::Top => (Vec3D::new(0.0, -1.0, 0.0), Vec3D::new(0.0, 0.0, 1.0)),
+            View::Front => (Vec3D::new(0.0, 0.0, 1.0), Vec3D::new(0.0, 1.0, 0.0)),
+            View::Side => (Vec3D::new(1.0, 0.0, 0.0), Vec3D::new(0.0, 1.0, 0.0)),
+        };
+        let target = &camera + &view_dir;
 
         // Make view matrix from camera
-        let mat_view = quick_inverse(&mat_camera);
+        let mat_view = make_look_at(&camera, &target, &up, Handedness::Left);
+
+        let mat_proj = match self.view {
+            View::Perspective => self.mat_proj,
+            _ => {
+                let half_width = self.ortho_size * WIDTH as f64 / HEIGHT as f64;
+                make_orthographic(
+                    -half_width,
+                    half_width,
+                    -self.ortho_size,
+                    self.ortho_size,
+                    NEAR,
+                    FAR,
+                    Handedness::Left,
+                )
+            }
+        };
+
+        // Cast a ray from the cursor into the mesh. The mesh is picked in its own
+        // space, so bring the ray back through the world matrix first
+        self.picked = None;
+        self.picked_tris.clear();
+        if self.picking {
+            if let Some((x, y)) = cursor {
+                let ray = screen_to_ray(
+                    x as f64,
+                    y as f64,
+                    WIDTH as f64,
+                    HEIGHT as f64,
+                    &mat_proj,
+                    &mat_view,
+                );
+                if let (Some(ray), Some(mat_world_inv)) = (ray, &mat_world_inv) {
+                    let ray = ray.transform(mat_world_inv);
+                    self.picked = self.bvh.pick(&ray, &self.mesh_cube).map(|mut hit| {
+                        hit.point = multiply_vector(&mat_world, &hit.point);
+                        hit
+                    });
+                }
+            }
+        }
+        let picked_index = self.picked.map(|hit| hit.index);
+
+        let mat_world_view = multiply_matrix(&mat_world, &mat_view);
+        let mat_view_proj = multiply_matrix(&mat_view, &mat_proj);
+
+        // Find the triangles that might be on screen. The frustum is built in the
+        // mesh's own space, so the mesh and BVH bounds can be tested directly. The
+        // whole object is checked with a bounding sphere and then its box, before
+        // walking the BVH chunks
+        self.cull_stats = CullStats::default();
+        let visible = if self.culling {
+            let frustum = Frustum::from_matrix(&multiply_matrix(&mat_world_view, &mat_proj));
+            let bounds = self.bvh.bounds();
+            let (center, radius) = bounds.bounding_sphere();
+            if frustum.test_sphere(&center, radius) == Visibility::Outside
+                || frustum.test_aabb(&bounds) == Visibility::Outside
+            {
+                self.cull_stats.objects_culled += 1;
+                self.cull_stats.triangles_culled += self.mesh_cube.tris.len();
+                vec![]
+            } else {
+                self.bvh.frustum_cull(&frustum, &mut self.cull_stats)
+            }
+        } else {
+            self.cull_stats.triangles_visible = self.mesh_cube.tris.len();
+            (0..self.mesh_cube.tris.len()).collect()
+        };
+
+        // Project the edges of the BVH boxes to the screen
+        self.debug_lines.clear();
+        if self.show_bvh {
+            for aabb in self.bvh.boxes_at_depth(self.bvh_depth) {
+                let corners = aabb.corners().map(|c| multiply_vector(&mat_world_view, &c));
+                for i in 0..8 {
+                    for bit in [1, 2, 4] {
+                        if i & bit != 0 {
+                            continue;
+                        }
+                        let (mut a, mut b) = (corners[i], corners[i | bit]);
+
+                        // Clip the edge against the near plane
+                        if a.z < NEAR && b.z < NEAR {
+                            continue;
+                        }
+                        if a.z < NEAR || b.z < NEAR {
+                            let t = (NEAR - a.z) / (b.z - a.z);
+                            let p = &a + &(&(&b - &a) * t);
+                            let p = Vec3D::new(p.x, p.y, NEAR);
+                            if a.z < NEAR {
+                                a = p;
+                            } else {
+                                b = p;
+                            }
+                        }
+
+                        self.debug_lines.push((
+                            project_to_screen(&mat_proj, &a),
+                            project_to_screen(&mat_proj, &b),
+                        ));
+                    }
+                }
+            }
+        }
+
+        // Lights for this frame, including the flashlight which follows the camera
+        let mut lighting = self.lighting.clone();
+        if self.flashlight {
+            lighting.add(Light::spot(
+                camera,
+                view_dir,
+                15.0,
+                25.0,
+                Attenuation::from_range(100.0),
+                [1.0, 0.95, 0.8],
+                1.5,
+            ));
+        }
+
+        // Specular highlights are seen from the camera, or from far along the
+        // view direction for the orthographic views
+        let eye = match self.view {
+            View::Perspective => camera,
+            _ => &camera - &(&view_dir * FAR),
+        };
 
         // Store triangles for rastering later
         let mut tris_to_raster = vec![];
 
         // Draw Triangles
-        for tri in &self.mesh_cube.tris {
-            let tri_transformed = Triangle::new_uv(
-                multiply_vector(&mat_world, &tri.p[0]),
-                multiply_vector(&mat_world, &tri.p[1]),
-                multiply_vector(&mat_world, &tri.p[2]),
-                tri.t[0],
-                tri.t[1],
-                tri.t[2],
-            );
+        let material = self.mesh_cube.material;
+        for index in visible {
+            let tri = &self.mesh_cube.tris[index];
+            let mut tri_transformed = *tri;
+            for i in 0..3 {
+                tri_transformed.p[i] = multiply_vector(&mat_world, &tri.p[i]);
+                tri_transformed.n[i] = multiply_direction(&mat_world, &tri.n[i]).normalise();
+            }
 
             // Calculate triangle normal
             // Get lines on either side of triangle
@@ -141,67 +1097,55 @@ impl Engine3D {
             // Normalize
             normal = normal.normalise();
 
-            // Get ray from triangle to camera
-            let camera_ray = &tri_transformed.p[0] - &self.camera;
-
-            // If ray is aligned with normal, then triangle is visible
-            if dot_product(&normal, &camera_ray) < 0.0 {
-                // Illumination
-                let mut light_direction = Vec3D::new(0.0, 1.0, -1.0);
-                light_direction = light_direction.normalise();
-
-                // How "aligned" are light direction and triangle surface normal?
-                let dp = dot_product(&light_direction, &normal).max(0.1);
-
-                // Choose colors
-                let c = get_color(dp);
-
-                // Convert world space --> view space
-                let mut tri_viewed = Triangle::new_uv(
-                    multiply_vector(&mat_view, &tri_transformed.p[0]),
-                    multiply_vector(&mat_view, &tri_transformed.p[1]),
-                    multiply_vector(&mat_view, &tri_transformed.p[2]),
-                    tri_transformed.t[0],
-                    tri_transformed.t[1],
-                    tri_transformed.t[2],
-                );
-                tri_viewed.col = c;
-
-                // Clip viewed triangle against near plane, this could form two additional
-                // triangles
-                let (clipped_triangles, clipped) = clip_against_plane(
-                    Vec3D::new(0.0, 0.0, 0.1),
-                    Vec3D::new(0.0, 0.0, 1.0),
-                    &tri_viewed,
-                );
-
-                for &clipped_tri in clipped.iter().take(clipped_triangles) {
-                    // Project triangles from 3D --> 2D
-                    let mut tri_projected = Triangle::new_uv(
-                        multiply_vector(&self.mat_proj, &clipped_tri.p[0]),
-                        multiply_vector(&self.mat_proj, &clipped_tri.p[1]),
-                        multiply_vector(&self.mat_proj, &clipped_tri.p[2]),
-                        clipped_tri.t[0],
-                        clipped_tri.t[1],
-                        clipped_tri.t[2],
-                    );
-                    tri_projected.col = clipped_tri.col;
+            // Get ray from triangle to camera. In the orthographic views every
+            // ray is parallel to the view direction
+            let camera_ray = match self.view {
+                View::Perspective => &tri_transformed.p[0] - &camera,
+                _ => view_dir,
+            };
 
-                    tri_projected.t[0].u /= tri_projected.p[0].w;
-                    tri_projected.t[1].u /= tri_projected.p[1].w;
-                    tri_projected.t[2].u /= tri_projected.p[2].w;
+            // If ray is aligned with normal, then triangle is front facing.
+            // Back faces that are drawn are lit from their own side
+            let front_facing = dot_product(&normal, &camera_ray) < 0.0;
+            if tri.cull.keeps(front_facing) {
+                if !front_facing {
+                    for n in &mut tri_transformed.n {
+                        *n = &*n * -1.0;
+                    }
+                }
 
-                    tri_projected.t[0].v /= tri_projected.p[0].w;
-                    tri_projected.t[1].v /= tri_projected.p[1].w;
-                    tri_projected.t[2].v /= tri_projected.p[2].w;
+                // Illumination. Per-pixel lighting is done in `draw` from the
+                // interpolated world positions and normals instead
+                tri_transformed.world = tri_transformed.p;
+                if material.shading == Shading::Vertex {
+                    for i in 0..3 {
+                        let (diffuse, specular) = lighting.blinn_phong_shadowed(
+                            &tri_transformed.p[i],
+                            &tri_transformed.n[i],
+                            &eye,
+                            &material,
+                            |light, p| self.shadow(light, p),
+                        );
+                        tri_transformed.diffuse[i] = diffuse;
+                        tri_transformed.specular[i] = specular;
+                    }
+                }
 
-                    tri_projected.t[0].w = 1.0 / tri_projected.p[0].w;
-                    tri_projected.t[1].w = 1.0 / tri_projected.p[1].w;
-                    tri_projected.t[2].w = 1.0 / tri_projected.p[2].w;
+                // Convert world space --> view space --> clip space
+                let mut tri_clip = tri_transformed;
+                for i in 0..3 {
+                    tri_clip.p[i] = multiply_vector(&mat_view_proj, &tri_transformed.p[i]);
+                }
 
-                    tri_projected.p[0] = &tri_projected.p[0] / tri_projected.p[0].w;
-                    tri_projected.p[1] = &tri_projected.p[1] / tri_projected.p[1].w;
-                    tri_projected.p[2] = &tri_projected.p[2] / tri_projected.p[2].w;
+                // Clip against all six planes of the view volume before the
+                // perspective divide, this could form several triangles
+                for tri_clipped in clip_triangle(&tri_clip) {
+                    // Divide by w, which also prepares the vertex attributes for
+                    // perspective correct interpolation
+                    let mut tri_projected = Triangle::from_vertices(
+                        &tri_clipped,
+                        tri_clipped.vertices().map(|v| v.perspective_divide()),
+                    );
 
                     // X/Y are inverted so put them back
                     tri_projected.p[0].x *= -1.0;
@@ -223,12 +1167,21 @@ impl Engine3D {
                     tri_projected.p[2].x *= 0.5 * WIDTH as f64;
                     tri_projected.p[2].y *= 0.5 * HEIGHT as f64;
 
+                    if picked_index == Some(index) {
+                        self.picked_tris.push(tri_projected);
+                    }
+
                     // Store triangles for sorting
                     tris_to_raster.push(tri_projected);
                 }
             }
         }
 
+        self.frame_lighting = lighting;
+        self.eye = eye;
+        self.frame_proj = mat_proj;
+        self.frame_view_proj = mat_view_proj;
+
         // Sort triangles from back to front
         // tris_to_raster.sort_by(|t1, t2| {
         //     let z1 = (t1.p[0].z + t1.p[1].z + t1.p[2].z) / 3.0;
@@ -239,74 +1192,58 @@ impl Engine3D {
         tris_to_raster
     }
 
-    fn draw(&self, frame: &mut [u8], tris_to_raster: Vec<Triangle>) {
-        // Clear screen
-        for pixel in frame.chunks_exact_mut(4) {
-            pixel.copy_from_slice(&[107, 229, 252, 0xff]);
-        }
-
-        let mut depth_buffer = [0.0; (WIDTH * HEIGHT) as usize];
-
-        for tri_to_raster in tris_to_raster {
-            // Clip triangles against all four screen edges, this could yield
-            // a bunch of triangles
-
-            // Add initial triangle
-            let mut list_triangles = vec![tri_to_raster];
-            let mut new_triangles = 1;
-
-            for p in 0..4 {
-                while new_triangles > 0 {
-                    // Take triangles from front of queue
-                    let test = list_triangles.remove(0);
-                    new_triangles -= 1;
-
-                    // Clip it against a plane. We only need to test each
-                    // subsequent plane, against subsequent new triangles
-                    // as all triangles after a plane clip are guaranteed
-                    // to lie on the inside of the plane. I like how this
-                    // comment is almost completely and utterly justified
-                    let (tris_to_add, clipped) = match p {
-                        0 => clip_against_plane(
-                            Vec3D::new(0.0, 0.0, 0.0),
-                            Vec3D::new(0.0, 1.0, 0.0),
-                            &test,
-                        ),
-                        1 => clip_against_plane(
-                            Vec3D::new(0.0, HEIGHT as f64 - 1.0, 0.0),
-                            Vec3D::new(0.0, -1.0, 0.0),
-                            &test,
-                        ),
-                        2 => clip_against_plane(
-                            Vec3D::new(0.0, 0.0, 0.0),
-                            Vec3D::new(1.0, 0.0, 0.0),
-                            &test,
-                        ),
-                        3 => clip_against_plane(
-                            Vec3D::new(WIDTH as f64 - 1.0, 0.0, 0.0),
-                            Vec3D::new(-1.0, 0.0, 0.0),
-                            &test,
-                        ),
-                        _ => (0, [Triangle::empty(); 2]),
-                    };
+    // Returns the depth buffer, for saving alongside the frame
+    fn draw(&self, frame: &mut [u8], tris_to_raster: Vec<Triangle>) -> Vec<f64> {
+        // Clear screen to the sky
+        self.sky.draw(frame, WIDTH, &self.frame_view_proj);
 
-                    // Clipping may yield a variable number of triangles, so
-                    // add these new ones to the back of the queue for subsequent
-                    // clipping against next planes
-                    for &tri in clipped.iter().take(tris_to_add) {
-                        list_triangles.push(tri);
-                    }
-                }
+        // Depth runs from 0.0 at the near plane to 1.0 at the far plane
+        let mut depth_buffer = vec![1.0; (WIDTH * HEIGHT) as usize];
 
-                new_triangles = list_triangles.len();
-            }
+        let material = &self.mesh_cube.material;
 
-            // Draw triangles
-            for t in list_triangles {
-                // fill_triangle(frame, WIDTH, &t);
-                textured_triangle(frame, WIDTH, &t, &self.spr_tex, &mut depth_buffer);
-                // draw_triangle(frame, WIDTH, &t, &[255, 255, 255, 0xff]);
-            }
+        // Triangles are already clipped to the screen in clip space
+        for t in tris_to_raster {
+            // fill_triangle(frame, WIDTH, &t);
+            rasterize_triangle(frame, WIDTH, &t, &mut depth_buffer, |v| {
+                // Models without a texture show their vertex colours
+                let texel = match &self.spr_tex {
+                    Some(tex) => sample_texture(tex, &v.t),
+                    None => get_color_rgb(v.color),
+                };
+                let (diffuse, specular) = match material.shading {
+                    Shading::Vertex => (v.diffuse, v.specular),
+                    Shading::Pixel => self.frame_lighting.blinn_phong_shadowed(
+                        &v.world,
+                        &v.n.normalise(),
+                        &self.eye,
+                        material,
+                        |light, p| self.shadow(light, p),
+                    ),
+                };
+                shade_texel(&texel, &diffuse, &specular)
+            });
+            // draw_triangle(frame, WIDTH, &t, &[255, 255, 255, 0xff]);
+        }
+
+        if let Some(fog) = &self.fog {
+            fog.apply(
+                frame,
+                WIDTH,
+                &depth_buffer,
+                &self.sky,
+                &self.frame_proj,
+                &self.frame_view_proj,
+            );
+        }
+
+        for (a, b) in &self.debug_lines {
+            draw_line(frame, WIDTH, a, b, &[255, 0, 255, 0xff]);
+        }
+
+        // Outline the picked triangle on top of everything else
+        for t in &self.picked_tris {
+            draw_triangle(frame, WIDTH, t, &[255, 255, 0, 0xff]);
         }
 
         // Draw depth buffer to screen
@@ -314,9 +1251,133 @@ impl Engine3D {
         //     let c = ((depth_buffer[i] * 4.0).tanh() * 255.0) as u8;
         //     pixel.copy_from_slice(&[c, c, c, 0xff]);
         // }
+
+        depth_buffer
+    }
+
+    // Save the drawn frame if a screenshot was asked for or recording is on
+    fn capture(&mut self, frame: &[u8], depth_buffer: &[f64]) {
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            self.screenshots.depth = self.capture_depth;
+            let saved = self
+                .screenshots
+                .write(frame, depth_buffer, WIDTH, &self.frame_proj);
+            self.status = Some(match saved {
+                Ok(path) => format!("Saved {}", path.display()),
+                Err(e) => {
+                    eprintln!("Error saving screenshot: {}", e);
+                    format!("Error saving screenshot: {}", e)
+                }
+            });
+        }
+
+        if let Some(recording) = &mut self.recording {
+            match recording.write(frame, depth_buffer, WIDTH, &self.frame_proj) {
+                Ok(path) => self.status = Some(format!("Recording {}", path.display())),
+                Err(e) => {
+                    eprintln!("Error recording frame: {}", e);
+                    self.status = Some(format!("Error recording frame: {}", e));
+                    self.recording = None;
+                }
+            }
+        }
+    }
+}
+
+// A loaded model's mesh, its texture if it brought one, and its terrain if it
+// is a heightmap image
+type Model = (Mesh, Option<DynamicImage>, Option<Terrain>);
+
+// Load a model by its file extension. A terrain's mesh starts at full detail
+fn load_model(filename: &str, has_tex: bool) -> Result<Model, LoadError> {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("png" | "jpg" | "jpeg" | "bmp" | "tga") => {
+            let heightmap = Heightmap::load(filename, TERRAIN_CELL_SIZE, TERRAIN_HEIGHT)?;
+            let terrain = Terrain::new(
+                heightmap,
+                TERRAIN_CHUNK_CELLS,
+                TERRAIN_LEVELS,
+                TERRAIN_LOD_DISTANCE,
+            );
+            Ok((terrain.mesh(), None, Some(terrain)))
+        }
+        _ => {
+            let (mesh, texture) = load_mesh(filename, has_tex)?;
+            Ok((mesh, texture, None))
+        }
+    }
+}
+
+// Load a model by its file extension. OBJ files are centered and read with
+// texture coordinates only if `has_tex` is set, and glTF scenes are merged
+// into a single mesh, returned with the base colour texture of its material
+fn load_mesh(filename: &str, has_tex: bool) -> Result<(Mesh, Option<DynamicImage>), LoadError> {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("stl") => Ok((load_stl(filename)?, None)),
+        Some("ply") => Ok((load_ply(filename)?, None)),
+        Some("gltf" | "glb") => {
+            let mut scene = load_gltf(filename)?;
+            let mesh = scene.flatten();
+            let texture = mesh
+                .material
+                .texture
+                .filter(|&i| i < scene.textures.len())
+                .map(|i| scene.textures.swap_remove(i));
+            Ok((mesh, texture))
+        }
+        _ => Ok((
+            Mesh::load(
+                filename,
+                LoadOptions {
+                    has_tex,
+                    center: true,
+                },
+            )?,
+            None,
+        )),
     }
 }
 
+// Project a view space point to screen space, the same way triangles are
+fn project_to_screen(mat_proj: &Mat4x4, p: &Vec3D) -> Vec3D {
+    let p = multiply_vector(mat_proj, p);
+    let p = &p / p.w;
+    Vec3D::new(
+        (1.0 - p.x) * 0.5 * WIDTH as f64,
+        (1.0 - p.y) * 0.5 * HEIGHT as f64,
+        p.z,
+    )
+}
+
+fn load_texture(filename: &str) -> Result<DynamicImage, ImageError> {
+    ImageReader::open(filename)?.decode()
+}
+
+// Print an error loading a file, and return it to be shown in the title
+fn load_error(filename: &str, error: impl fmt::Display) -> String {
+    let error = format!("{}: {}", filename, error);
+    eprintln!("Error loading {}", error);
+    error
+}
+
+fn build_pixels(window: &Window, vsync: bool) -> Pixels {
+    let window_size = window.inner_size();
+    let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, window);
+    PixelsBuilder::new(WIDTH as u32, HEIGHT as u32, surface_texture)
+        .enable_vsync(vsync)
+        .build()
+        .unwrap()
+}
+
 fn main() {
     let event_loop = EventLoop::new().unwrap();
     let mut input = WinitInputHelper::new();
@@ -329,37 +1390,144 @@ fn main() {
             .unwrap()
     };
 
-    let mut pixels = {
-        let window_size = window.inner_size();
-        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(WIDTH as u32, HEIGHT as u32, surface_texture).unwrap()
-    };
+    let mut vsync = true;
+    let mut pixels = Some(build_pixels(&window, vsync));
 
-    let mut engine = Engine3D::new();
+    let mut engine = Engine3D::new(std::env::args().nth(1));
 
+    let mut timestep = FixedTimestep::new(STEPS_PER_SECOND);
+    let mut limiter = FrameLimiter::new(None);
+    let mut fps = FpsCounter::new();
     let mut last_frame_time = Instant::now();
 
     event_loop
         .run(move |event, elwt| {
+            // Input is gathered until all pending events are handled, then a
+            // redraw is asked for once the frame limiter allows it
             if input.update(&event) {
                 if input.key_pressed(KeyCode::Escape) || input.close_requested() {
                     elwt.exit();
+                    return;
                 }
 
-                let tris_to_raster = engine.update(&input);
-                engine.draw(pixels.frame_mut(), tris_to_raster);
+                engine.handle_keys(&input);
+                if input.key_pressed(KeyCode::KeyV) && input.held_control() {
+                    vsync = !vsync;
+                    // Vsync is fixed when the surface is made, so the old one
+                    // is dropped before making a new one for the window
+                    drop(pixels.take());
+                    pixels = Some(build_pixels(&window, vsync));
+                } else if input.key_pressed(KeyCode::KeyV) {
+                    limiter.max_fps = match limiter.max_fps {
+                        None => Some(FRAME_CAP),
+                        Some(_) => None,
+                    };
+                }
 
-                if let Err(e) = pixels.render() {
-                    println!("{}", e);
-                    elwt.exit();
+                match limiter.wait_until(Instant::now()) {
+                    Some(next_frame) => elwt.set_control_flow(ControlFlow::WaitUntil(next_frame)),
+                    None => {
+                        elwt.set_control_flow(ControlFlow::Poll);
+                        window.request_redraw();
+                    }
                 }
+            }
+
+            let Event::WindowEvent {
+                event: WindowEvent::RedrawRequested,
+                ..
+            } = event
+            else {
+                return;
+            };
 
-                engine.elapsed_time = last_frame_time.elapsed();
-                last_frame_time = Instant::now();
+            let Some(pixels) = pixels.as_mut() else {
+                return;
+            };
 
-                let fps = 1.0 / engine.elapsed_time.as_secs_f64();
-                window.set_title(&format!("Engine 3D - FPS: {:.0}", fps));
+            let now = Instant::now();
+            let frame_time = now - last_frame_time;
+            last_frame_time = now;
+            limiter.frame_started(now);
+            fps.tick(frame_time);
+
+            // A replay runs exactly one recorded step per frame, however long
+            // frames take, so it draws the same frames every time
+            let alpha = if let Some(dt) = engine.replay_dt() {
+                engine.step(&input, dt);
+                1.0
+            } else {
+                for _ in 0..timestep.advance(frame_time) {
+                    engine.step(&input, timestep.step);
+                }
+                timestep.alpha()
+            };
+
+            let cursor = input
+                .cursor()
+                .and_then(|c| pixels.window_pos_to_pixel(c).ok());
+
+            let tris_to_raster = engine.update(alpha, cursor);
+            let depth_buffer = engine.draw(pixels.frame_mut(), tris_to_raster);
+            engine.capture(pixels.frame(), &depth_buffer);
+
+            if let Err(e) = pixels.render() {
+                println!("{}", e);
+                elwt.exit();
+            }
+
+            let stats = engine.cull_stats;
+            let mut title = format!("Engine 3D - FPS: {:.0}", fps.fps());
+            if let Some(max_fps) = limiter.max_fps {
+                title += &format!(" (cap {:.0})", max_fps);
+            }
+            if !vsync {
+                title += " (no vsync)";
+            }
+            title += &format!(
+                " - Culled: {} objects, {}/{} chunks, {}/{} tris",
+                stats.objects_culled,
+                stats.chunks_culled,
+                stats.chunks_tested,
+                stats.triangles_culled,
+                stats.triangles_culled + stats.triangles_visible
+            );
+            if engine.flythrough_active {
+                title += &format!(
+                    " - Flythrough {:.1}/{:.1}s",
+                    engine.playback.time, engine.playback.duration
+                );
+                if !engine.playback.playing {
+                    title += " (paused)";
+                }
+            }
+            if let Some(lod) = engine.lod.as_ref().filter(|_| engine.lod_enabled) {
+                title += &format!(
+                    " - LOD {}/{} ({} tris)",
+                    engine.lod_level,
+                    lod.levels.len() - 1,
+                    engine.mesh_cube.tris.len()
+                );
+            }
+            if engine.walking && engine.terrain.is_some() {
+                title += " - Walking";
+            }
+            if let Some(hit) = engine.picked {
+                title += &format!(
+                    " - Triangle {} at ({:.2}, {:.2}, {:.2})",
+                    hit.index, hit.point.x, hit.point.y, hit.point.z
+                );
+            }
+            if let Some(status) = &engine.status {
+                title += &format!(" - {}", status);
+            }
+            for error in [&engine.mesh_error, &engine.tex_error]
+                .into_iter()
+                .flatten()
+            {
+                title += &format!(" - Error loading {}", error);
             }
+            window.set_title(&title);
         })
         .unwrap();
 }
@@ -4,7 +4,22 @@ use crate::vec3d::{cross_product, dot_product};
 
 use super::vec3d::Vec3D;
 
-#[derive(Debug)]
+// Matrices are applied to row vectors (`v * M`), so translation lives in the
+// bottom row and `multiply_matrix(&a, &b)` applies `a` first, then `b`.
+//
+// The engine itself works in a left-handed view space: the camera looks down +z,
+// +y is up, and projected depth runs from 0.0 at the near plane to 1.0 at the far
+// plane. The `Handedness` switch lets view and projection matrices be built for a
+// right-handed space instead, where the camera looks down -z. Either way the clip
+// space produced by the projection functions is the same, so the rest of the
+// pipeline does not need to know which convention was used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Handedness {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct Mat4x4 {
     pub m: [[f64; 4]; 4],
 }
@@ -31,6 +46,17 @@ pub fn multiply_vector(m: &Mat4x4, i: &Vec3D) -> Vec3D {
     }
 }
 
+// Transform a direction, such as a normal, ignoring translation. Normals only
+// stay perpendicular to their surface if `m` has no non-uniform scaling
+pub fn multiply_direction(m: &Mat4x4, i: &Vec3D) -> Vec3D {
+    Vec3D {
+        x: i.x * m.m[0][0] + i.y * m.m[1][0] + i.z * m.m[2][0],
+        y: i.x * m.m[0][1] + i.y * m.m[1][1] + i.z * m.m[2][1],
+        z: i.x * m.m[0][2] + i.y * m.m[1][2] + i.z * m.m[2][2],
+        w: 0.0,
+    }
+}
+
 pub fn multiply_matrix(m1: &Mat4x4, m2: &Mat4x4) -> Mat4x4 {
     let mut matrix = Mat4x4::default();
     for c in 0..4 {
@@ -78,6 +104,37 @@ pub fn point_at(pos: &Vec3D, target: &Vec3D, up: &Vec3D) -> Mat4x4 {
     matrix
 }
 
+// Builds a view matrix for a camera at `eye` looking towards `target`. For
+// `Handedness::Left` this is the same as `quick_inverse(&point_at(...))`.
+pub fn make_look_at(eye: &Vec3D, target: &Vec3D, up: &Vec3D, handedness: Handedness) -> Mat4x4 {
+    match handedness {
+        Handedness::Left => quick_inverse(&point_at(eye, target, up)),
+        Handedness::Right => {
+            // Camera looks down -z, so the basis is built from the reversed
+            // forward direction
+            let back = (eye - target).normalise();
+            let right = cross_product(up, &back).normalise();
+            let new_up = cross_product(&back, &right);
+
+            let mut matrix = Mat4x4::default();
+            matrix.m[0][0] = right.x;
+            matrix.m[0][1] = right.y;
+            matrix.m[0][2] = right.z;
+            matrix.m[1][0] = new_up.x;
+            matrix.m[1][1] = new_up.y;
+            matrix.m[1][2] = new_up.z;
+            matrix.m[2][0] = back.x;
+            matrix.m[2][1] = back.y;
+            matrix.m[2][2] = back.z;
+            matrix.m[3][0] = eye.x;
+            matrix.m[3][1] = eye.y;
+            matrix.m[3][2] = eye.z;
+            matrix.m[3][3] = 1.0;
+            quick_inverse(&matrix)
+        }
+    }
+}
+
 pub fn quick_inverse(m: &Mat4x4) -> Mat4x4 {
     let mut matrix = Mat4x4::default();
     matrix.m[0][0] = m.m[0][0];
@@ -102,6 +159,59 @@ pub fn quick_inverse(m: &Mat4x4) -> Mat4x4 {
     matrix
 }
 
+// General inverse using Gauss-Jordan elimination with partial pivoting. Returns
+// `None` if the matrix is singular. Prefer `quick_inverse` for rotation and
+// translation only matrices
+pub fn inverse(m: &Mat4x4) -> Option<Mat4x4> {
+    let mut a = m.m;
+    let mut inv = make_identity().m;
+
+    for c in 0..4 {
+        // Find the row with the largest value in this column to pivot on
+        let mut pivot = c;
+        for r in c + 1..4 {
+            if a[r][c].abs() > a[pivot][c].abs() {
+                pivot = r;
+            }
+        }
+        if a[pivot][c].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(c, pivot);
+        inv.swap(c, pivot);
+
+        // Scale the pivot row so the pivot becomes 1
+        let p = a[c][c];
+        for k in 0..4 {
+            a[c][k] /= p;
+            inv[c][k] /= p;
+        }
+
+        // Eliminate this column from every other row
+        for r in 0..4 {
+            if r != c {
+                let f = a[r][c];
+                for k in 0..4 {
+                    a[r][k] -= f * a[c][k];
+                    inv[r][k] -= f * inv[c][k];
+                }
+            }
+        }
+    }
+
+    Some(Mat4x4 { m: inv })
+}
+
+pub fn transpose(m: &Mat4x4) -> Mat4x4 {
+    let mut matrix = Mat4x4::default();
+    for (r, row) in m.m.iter().enumerate() {
+        for (c, v) in row.iter().enumerate() {
+            matrix.m[c][r] = *v;
+        }
+    }
+    matrix
+}
+
 pub fn make_identity() -> Mat4x4 {
     let mut matrix = Mat4x4::default();
     matrix.m[0][0] = 1.0;
@@ -144,6 +254,38 @@ pub fn make_rotation_z(angle: f64) -> Mat4x4 {
     matrix
 }
 
+// Rotates about an arbitrary axis through the origin. Uses the same sign
+// convention as `make_rotation_x` and `make_rotation_z`; note that
+// `make_rotation_y(angle)` matches `make_rotation_axis(&up, -angle)`.
+pub fn make_rotation_axis(axis: &Vec3D, angle: f64) -> Mat4x4 {
+    let a = axis.normalise();
+    let c = angle.cos();
+    let s = angle.sin();
+    let t = 1.0 - c;
+
+    let mut matrix = Mat4x4::default();
+    matrix.m[0][0] = c + a.x * a.x * t;
+    matrix.m[0][1] = a.x * a.y * t + a.z * s;
+    matrix.m[0][2] = a.x * a.z * t - a.y * s;
+    matrix.m[1][0] = a.x * a.y * t - a.z * s;
+    matrix.m[1][1] = c + a.y * a.y * t;
+    matrix.m[1][2] = a.y * a.z * t + a.x * s;
+    matrix.m[2][0] = a.x * a.z * t + a.y * s;
+    matrix.m[2][1] = a.y * a.z * t - a.x * s;
+    matrix.m[2][2] = c + a.z * a.z * t;
+    matrix.m[3][3] = 1.0;
+    matrix
+}
+
+pub fn make_scale(x: f64, y: f64, z: f64) -> Mat4x4 {
+    let mut matrix = Mat4x4::default();
+    matrix.m[0][0] = x;
+    matrix.m[1][1] = y;
+    matrix.m[2][2] = z;
+    matrix.m[3][3] = 1.0;
+    matrix
+}
+
 pub fn make_translation(x: f64, y: f64, z: f64) -> Mat4x4 {
     let mut matrix = Mat4x4::default();
     matrix.m[0][0] = 1.0;
@@ -157,13 +299,55 @@ pub fn make_translation(x: f64, y: f64, z: f64) -> Mat4x4 {
 }
 
 pub fn make_projection(fov: f64, aspect_ratio: f64, near: f64, far: f64) -> Mat4x4 {
+    make_perspective(fov, aspect_ratio, near, far, Handedness::Left)
+}
+
+// `aspect_ratio` is height / width, as in `make_projection`
+pub fn make_perspective(
+    fov: f64,
+    aspect_ratio: f64,
+    near: f64,
+    far: f64,
+    handedness: Handedness,
+) -> Mat4x4 {
     let fov_rad = 1.0 / (fov * 0.5 / 180.0 * PI).tan();
+    // A right-handed view space has the camera looking down -z, so depth is -z
+    let sign = match handedness {
+        Handedness::Left => 1.0,
+        Handedness::Right => -1.0,
+    };
     let mut matrix = Mat4x4::default();
     matrix.m[0][0] = aspect_ratio * fov_rad;
     matrix.m[1][1] = fov_rad;
-    matrix.m[2][2] = far / (far - near);
+    matrix.m[2][2] = sign * far / (far - near);
     matrix.m[3][2] = (-far * near) / (far - near);
-    matrix.m[2][3] = 1.0;
+    matrix.m[2][3] = sign;
     matrix.m[3][3] = 0.0;
     matrix
 }
+
+// Maps the box [left, right] x [bottom, top] x [near, far] (distances along the
+// view direction) to x, y in -1.0..1.0 and depth in 0.0..1.0, with w = 1.0
+pub fn make_orthographic(
+    left: f64,
+    right: f64,
+    bottom: f64,
+    top: f64,
+    near: f64,
+    far: f64,
+    handedness: Handedness,
+) -> Mat4x4 {
+    let sign = match handedness {
+        Handedness::Left => 1.0,
+        Handedness::Right => -1.0,
+    };
+    let mut matrix = Mat4x4::default();
+    matrix.m[0][0] = 2.0 / (right - left);
+    matrix.m[1][1] = 2.0 / (top - bottom);
+    matrix.m[2][2] = sign / (far - near);
+    matrix.m[3][0] = -(right + left) / (right - left);
+    matrix.m[3][1] = -(top + bottom) / (top - bottom);
+    matrix.m[3][2] = -near / (far - near);
+    matrix.m[3][3] = 1.0;
+    matrix
+}
@@ -0,0 +1,69 @@
+// Where lighting is evaluated. Per-vertex lighting is cheaper and interpolates
+// the result across each triangle; per-pixel lighting interpolates the normal
+// and position instead, which gives sharper specular highlights
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shading {
+    Vertex,
+    Pixel,
+}
+
+// Which faces are skipped when drawing and picking. Faces are front facing
+// when their winding makes their normal point towards the camera. Back faces
+// that are drawn are lit as if their normals pointed the other way, so both
+// sides of a single-sided surface look lit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullMode {
+    Back,
+    Front,
+    None,
+}
+
+impl CullMode {
+    pub fn keeps(self, front_facing: bool) -> bool {
+        match self {
+            CullMode::Back => front_facing,
+            CullMode::Front => !front_facing,
+            CullMode::None => true,
+        }
+    }
+}
+
+// Surface properties for the Blinn-Phong lighting model. Colours run from 0.0
+// to 1.0 per channel. The diffuse colour multiplies the texture, and emissive
+// light is added regardless of the lights in the scene. `texture` indexes the
+// textures of the scene the material was loaded with, if any
+#[derive(Clone, Copy, Debug)]
+pub struct Material {
+    pub diffuse: [f64; 3],
+    pub specular: [f64; 3],
+    pub shininess: f64,
+    pub emissive: [f64; 3],
+    pub shading: Shading,
+    pub cull: CullMode,
+    pub texture: Option<usize>,
+}
+
+impl Material {
+    pub fn new(diffuse: [f64; 3], specular: [f64; 3], shininess: f64, emissive: [f64; 3]) -> Self {
+        Self {
+            diffuse,
+            specular,
+            shininess,
+            emissive,
+            shading: Shading::Vertex,
+            cull: CullMode::Back,
+            texture: None,
+        }
+    }
+
+    // Dark diffuse and a bright, tight highlight
+    pub fn metal(color: [f64; 3]) -> Self {
+        Self::new(color.map(|c| c * 0.4), color, 64.0, [0.0; 3])
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::new([1.0; 3], [0.0; 3], 32.0, [0.0; 3])
+    }
+}
@@ -1,57 +1,64 @@
 use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
     fs::File,
-    io::{BufRead, BufReader},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
 };
 
-use crate::{triangle::Triangle, vec2d::Vec2D, vec3d::Vec3D};
-
-// pub CUBE: Mesh = Mesh::new(vec![
-//     // SOUTH
-//     [
-//         0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0,
-//     ],
-//     [
-//         0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0,
-//     ],
-//     // EAST
-//     [
-//         1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0,
-//     ],
-//     [
-//         1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0,
-//     ],
-//     // NORTH
-//     [
-//         1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0,
-//     ],
-//     [
-//         1.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0,
-//     ],
-//     // WEST
-//     [
-//         0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0,
-//     ],
-//     [
-//         0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0,
-//     ],
-//     // TOP
-//     [
-//         0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0,
-//     ],
-//     [
-//         0.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0,
-//     ],
-//     // BOTTOM
-//     [
-//         1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0,
-//     ],
-//     [
-//         1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0,
-//     ],
-// ]);
+use crate::{
+    aabb::Aabb,
+    mat4x4::{
+        inverse, make_rotation_axis, make_scale, make_translation, multiply_direction,
+        multiply_vector, transpose, Mat4x4,
+    },
+    material::Material,
+    triangle::Triangle,
+    vec2d::Vec2D,
+    vec3d::{length, Vec3D},
+};
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    // Errors from formats that aren't read line by line
+    Format(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            LoadError::Format(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+// How `Mesh::load` reads an OBJ file. `has_tex` reads texture coordinates from
+// the faces, and `center` moves the mesh so the average of the file's vertices
+// is at the origin. Keep `center` off for meshes that share a coordinate
+// system, like the parts of a level
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoadOptions {
+    pub has_tex: bool,
+    pub center: bool,
+}
 
+#[derive(Clone)]
 pub struct Mesh {
     pub tris: Vec<Triangle>,
+    pub material: Material,
 }
 
 impl Mesh {
@@ -67,92 +74,114 @@ impl Mesh {
                 Vec2D::new(tri[13], tri[14]),
             ));
         }
-        Self { tris }
+        Self {
+            tris,
+            material: Material::default(),
+        }
+    }
+
+    pub fn from_file(filename: &str, options: LoadOptions) -> Self {
+        Self::load(filename, options).expect("Error loading mesh!")
     }
 
-    pub fn from_file(filename: &str, has_tex: bool) -> Self {
+    // Like `from_file`, but returns an error instead of panicking when the
+    // file can't be read or is malformed
+    pub fn load(filename: &str, options: LoadOptions) -> Result<Self, LoadError> {
+        let has_tex = options.has_tex;
         let mut verts: Vec<Vec3D> = vec![];
+        let mut colors: Vec<[f64; 3]> = vec![];
         let mut texs: Vec<Vec2D> = vec![];
+        let mut normals: Vec<Vec3D> = vec![];
         let mut tris: Vec<Triangle> = vec![];
 
         let mut x_a = 0.0;
         let mut y_a = 0.0;
         let mut z_a = 0.0;
 
-        let file = File::open(filename).expect("Error opening file!");
+        let file = File::open(filename)?;
         let reader = BufReader::new(file);
 
-        for line in reader.lines() {
-            let line = line.unwrap();
+        for (i, line) in reader.lines().enumerate() {
+            let line_number = i + 1;
+            let error = |message: &str| LoadError::Parse {
+                line: line_number,
+                message: message.to_string(),
+            };
+
+            let line = line?;
             let mut line = line.split_ascii_whitespace();
             if let Some(c) = line.next() {
                 match c {
                     "v" => {
-                        let nums = line
-                            .map(|n| n.parse::<f64>().unwrap())
-                            .collect::<Vec<f64>>();
+                        let nums = parse_floats(line, 3).ok_or_else(|| error("bad vertex"))?;
                         let vert = Vec3D::new(nums[0], nums[1], nums[2]);
                         x_a += nums[0];
                         y_a += nums[1];
                         z_a += nums[2];
                         verts.push(vert);
+                        // Some tools write a vertex colour after the position
+                        colors.push(match nums[..] {
+                            [_, _, _, r, g, b] => [r, g, b],
+                            _ => [1.0; 3],
+                        });
                     }
                     "vt" => {
-                        let nums = line
-                            .map(|n| n.parse::<f64>().unwrap())
-                            .collect::<Vec<f64>>();
+                        let nums =
+                            parse_floats(line, 2).ok_or_else(|| error("bad texture coordinate"))?;
                         let tex = Vec2D::new(nums[0], 1.0 - nums[1]);
                         texs.push(tex);
                     }
-                    "f" => {
-                        if !has_tex {
-                            let nums = line
-                                .map(|n| n.parse::<usize>().unwrap() - 1)
-                                .collect::<Vec<usize>>();
-                            let tri = Triangle::new(verts[nums[0]], verts[nums[1]], verts[nums[2]]);
-                            tris.push(tri);
+                    "vn" => {
+                        let nums = parse_floats(line, 3).ok_or_else(|| error("bad normal"))?;
+                        let normal = Vec3D::new(nums[0], nums[1], nums[2]);
+                        normals.push(if length(&normal) > 0.0 {
+                            normal.normalise()
                         } else {
-                            let mut vert_indices = vec![];
-                            let mut tex_indices = vec![];
-                            line.for_each(|p| {
-                                let mut pair =
-                                    p.split('/').map(|n| n.parse::<usize>().unwrap() - 1);
-                                vert_indices.push(pair.next().unwrap());
-                                tex_indices.push(pair.next().unwrap());
-                            });
-
-                            if vert_indices.len() == 3 {
-                                // Normal Triangle
-                                let tri = Triangle::new_uv(
-                                    verts[vert_indices[0]],
-                                    verts[vert_indices[1]],
-                                    verts[vert_indices[2]],
-                                    texs[tex_indices[0]],
-                                    texs[tex_indices[1]],
-                                    texs[tex_indices[2]],
-                                );
-                                tris.push(tri);
+                            normal
+                        });
+                    }
+                    "f" => {
+                        let mut vert_indices = vec![];
+                        let mut tex_indices = vec![];
+                        let mut normal_indices = vec![];
+                        for p in line {
+                            let mut indices = p.split('/');
+                            let vert = parse_index(indices.next(), verts.len())
+                                .ok_or_else(|| error("bad vertex index"))?;
+                            vert_indices.push(vert);
+                            let tex = indices.next();
+                            if has_tex {
+                                let tex = parse_index(tex, texs.len())
+                                    .ok_or_else(|| error("bad texture index"))?;
+                                tex_indices.push(tex);
+                            }
+                            if let Some(normal) = indices.next() {
+                                let normal = parse_index(Some(normal), normals.len())
+                                    .ok_or_else(|| error("bad normal index"))?;
+                                normal_indices.push(normal);
+                            }
+                        }
+
+                        if vert_indices.len() < 3 {
+                            return Err(error("face has fewer than 3 vertices"));
+                        }
+
+                        // Split quads and larger polygons into a fan
+                        for k in 1..vert_indices.len() - 1 {
+                            let corners = [0, k, k + 1];
+                            let [a, b, c] = corners.map(|j| verts[vert_indices[j]]);
+                            let mut tri = if has_tex {
+                                let [ta, tb, tc] = corners.map(|j| texs[tex_indices[j]]);
+                                Triangle::new_uv(a, b, c, ta, tb, tc)
                             } else {
-                                // Quad
-                                let tri1 = Triangle::new_uv(
-                                    verts[vert_indices[0]],
-                                    verts[vert_indices[1]],
-                                    verts[vert_indices[2]],
-                                    texs[tex_indices[0]],
-                                    texs[tex_indices[1]],
-                                    texs[tex_indices[2]],
-                                );
-                                tris.push(tri1);
-                                let tri2 = Triangle::new_uv(
-                                    verts[vert_indices[0]],
-                                    verts[vert_indices[2]],
-                                    verts[vert_indices[3]],
-                                    texs[tex_indices[0]],
-                                    texs[tex_indices[2]],
-                                    texs[tex_indices[3]],
-                                );
-                                tris.push(tri2);
+                                Triangle::new(a, b, c)
+                            };
+                            // Without normals for every corner the face normal is kept
+                            if normal_indices.len() == vert_indices.len() {
+                                tri.n = corners.map(|j| normals[normal_indices[j]]);
                             }
+                            tri.color = corners.map(|j| colors[vert_indices[j]]);
+                            tris.push(tri);
                         }
                     }
                     _ => {}
@@ -160,18 +189,293 @@ impl Mesh {
             }
         }
 
-        let x_a = x_a / verts.len() as f64;
-        let y_a = y_a / verts.len() as f64;
-        let z_a = z_a / verts.len() as f64;
+        let mut mesh = Self {
+            tris,
+            material: Material::default(),
+        };
+        if options.center && !verts.is_empty() {
+            let n = verts.len() as f64;
+            mesh.translate(-x_a / n, -y_a / n, -z_a / n);
+        }
+        Ok(mesh)
+    }
+
+    // Write the mesh as an OBJ file with positions, texture coordinates and
+    // normals, sharing identical values between faces. Vertex colours follow
+    // the positions, as many tools accept, unless every vertex is white
+    pub fn save_obj(&self, filename: &str) -> io::Result<()> {
+        self.write_obj(filename, None)
+    }
+
+    // Like `save_obj`, and also write the material to an MTL file of the same
+    // name next to it, with `texture` as its diffuse map
+    pub fn save_obj_with_mtl(&self, filename: &str, texture: Option<&str>) -> io::Result<()> {
+        let mtl_path = Path::new(filename).with_extension("mtl");
+        let mut out = BufWriter::new(File::create(&mtl_path)?);
+        let m = &self.material;
+        writeln!(out, "newmtl material")?;
+        writeln!(out, "Kd {} {} {}", m.diffuse[0], m.diffuse[1], m.diffuse[2])?;
+        writeln!(
+            out,
+            "Ks {} {} {}",
+            m.specular[0], m.specular[1], m.specular[2]
+        )?;
+        writeln!(out, "Ns {}", m.shininess)?;
+        writeln!(
+            out,
+            "Ke {} {} {}",
+            m.emissive[0], m.emissive[1], m.emissive[2]
+        )?;
+        if let Some(texture) = texture {
+            writeln!(out, "map_Kd {}", texture)?;
+        }
+        out.flush()?;
+
+        // The OBJ refers to the MTL by a path relative to itself
+        let mtl_name = mtl_path.file_name().unwrap().to_string_lossy();
+        self.write_obj(filename, Some(&mtl_name))
+    }
 
-        for tri in &mut tris {
-            for vec in &mut tri.p {
-                vec.x -= x_a;
-                vec.y -= y_a;
-                vec.z -= z_a;
+    fn write_obj(&self, filename: &str, mtl: Option<&str>) -> io::Result<()> {
+        let with_colors = self
+            .tris
+            .iter()
+            .any(|tri| tri.color.iter().any(|c| *c != [1.0; 3]));
+
+        let mut positions = Pool::default();
+        let mut tex_coords = Pool::default();
+        let mut normals = Pool::default();
+        let mut faces = vec![];
+        for tri in &self.tris {
+            let face: [[usize; 3]; 3] = std::array::from_fn(|i| {
+                let p = &tri.p[i];
+                let [r, g, b] = if with_colors { tri.color[i] } else { [1.0; 3] };
+                let t = &tri.t[i];
+                let n = &tri.n[i];
+                [
+                    positions.add([p.x, p.y, p.z, r, g, b]),
+                    tex_coords.add([t.u, 1.0 - t.v]),
+                    normals.add([n.x, n.y, n.z]),
+                ]
+            });
+            faces.push(face);
+        }
+
+        let mut out = BufWriter::new(File::create(filename)?);
+        if let Some(mtl) = mtl {
+            writeln!(out, "mtllib {}", mtl)?;
+        }
+        for [x, y, z, r, g, b] in &positions.values {
+            if with_colors {
+                writeln!(out, "v {} {} {} {} {} {}", x, y, z, r, g, b)?;
+            } else {
+                writeln!(out, "v {} {} {}", x, y, z)?;
             }
         }
+        for [u, v] in &tex_coords.values {
+            writeln!(out, "vt {} {}", u, v)?;
+        }
+        for [x, y, z] in &normals.values {
+            writeln!(out, "vn {} {} {}", x, y, z)?;
+        }
+        if mtl.is_some() {
+            writeln!(out, "usemtl material")?;
+        }
+        for face in &faces {
+            write!(out, "f")?;
+            for [v, t, n] in face {
+                write!(out, " {}/{}/{}", v, t, n)?;
+            }
+            writeln!(out)?;
+        }
+        out.flush()
+    }
+
+    // Transform every triangle in place. Normals are transformed by the
+    // inverse transpose so they stay perpendicular under non-uniform scaling,
+    // and a matrix that mirrors the mesh also reverses the winding so faces
+    // keep pointing outwards
+    pub fn transform(&mut self, m: &Mat4x4) {
+        let mat_normal = inverse(m).map_or(*m, |inv| transpose(&inv));
+        let a = &m.m;
+        let determinant = a[0][0] * (a[1][1] * a[2][2] - a[1][2] * a[2][1])
+            - a[0][1] * (a[1][0] * a[2][2] - a[1][2] * a[2][0])
+            + a[0][2] * (a[1][0] * a[2][1] - a[1][1] * a[2][0]);
+
+        for tri in &mut self.tris {
+            for i in 0..3 {
+                tri.p[i] = multiply_vector(m, &tri.p[i]);
+                let n = multiply_direction(&mat_normal, &tri.n[i]);
+                if length(&n) > 0.0 {
+                    tri.n[i] = n.normalise();
+                }
+            }
+            if determinant < 0.0 {
+                tri.flip();
+            }
+        }
+    }
+
+    pub fn translate(&mut self, x: f64, y: f64, z: f64) {
+        self.transform(&make_translation(x, y, z));
+    }
+
+    // Rotate about an axis through the origin, by an angle in radians
+    pub fn rotate(&mut self, axis: &Vec3D, angle: f64) {
+        self.transform(&make_rotation_axis(axis, angle));
+    }
+
+    pub fn scale(&mut self, x: f64, y: f64, z: f64) {
+        self.transform(&make_scale(x, y, z));
+    }
+
+    // Move the center of the bounding box to the origin
+    pub fn center(&mut self) {
+        let bounds = self.bounds();
+        if bounds.is_empty() {
+            return;
+        }
+        let c = bounds.center();
+        self.translate(-c.x, -c.y, -c.z);
+    }
+
+    // Center the mesh and scale it evenly so its bounding box fits in a unit
+    // cube around the origin, with the longest side exactly 1
+    pub fn normalize(&mut self) {
+        self.center();
+        let size = self.bounds().size();
+        let longest = size.x.max(size.y).max(size.z);
+        if longest > 0.0 {
+            let s = 1.0 / longest;
+            self.scale(s, s, s);
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for tri in &self.tris {
+            for p in &tri.p {
+                aabb.expand(p);
+            }
+        }
+        aabb
+    }
+}
+
+// Parse at least `count` floats, ignoring any extra components like w
+fn parse_floats<'a>(nums: impl Iterator<Item = &'a str>, count: usize) -> Option<Vec<f64>> {
+    let nums = nums
+        .map(|n| n.parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    (nums.len() >= count).then_some(nums)
+}
+
+// Parse a 1-based OBJ index into a 0-based index below `len`
+fn parse_index(index: Option<&str>, len: usize) -> Option<usize> {
+    let index = index?.parse::<usize>().ok()?;
+    (1..=len).contains(&index).then(|| index - 1)
+}
+
+// Distinct values written to an OBJ file, numbered from 1 in the order they
+// were first added
+struct Pool<const N: usize> {
+    index: HashMap<[u64; N], usize>,
+    values: Vec<[f64; N]>,
+}
+
+impl<const N: usize> Default for Pool<N> {
+    fn default() -> Self {
+        Self {
+            index: HashMap::new(),
+            values: vec![],
+        }
+    }
+}
+
+impl<const N: usize> Pool<N> {
+    fn add(&mut self, value: [f64; N]) -> usize {
+        // Adding 0.0 turns -0.0 into 0.0, so the two are treated as equal
+        let key = value.map(|v| (v + 0.0).to_bits());
+        *self.index.entry(key).or_insert_with(|| {
+            self.values.push(value);
+            self.values.len()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An octahedron around the origin, so loading it back doesn't move it, with
+    // a different colour and texture coordinate at each corner
+    fn octahedron() -> Mesh {
+        let p = [
+            Vec3D::new(1.0, 0.0, 0.0),
+            Vec3D::new(-1.0, 0.0, 0.0),
+            Vec3D::new(0.0, 1.0, 0.0),
+            Vec3D::new(0.0, -1.0, 0.0),
+            Vec3D::new(0.0, 0.0, 1.0),
+            Vec3D::new(0.0, 0.0, -1.0),
+        ];
+        let faces = [
+            [0, 2, 4],
+            [2, 1, 4],
+            [1, 3, 4],
+            [3, 0, 4],
+            [2, 0, 5],
+            [1, 2, 5],
+            [3, 1, 5],
+            [0, 3, 5],
+        ];
+        let mut mesh = Mesh::new(vec![]);
+        for face in faces {
+            let [a, b, c] = face.map(|i| p[i]);
+            let [ta, tb, tc] = face.map(|i| Vec2D::new(i as f64 * 0.125, 1.0 - i as f64 * 0.25));
+            let mut tri = Triangle::new_uv(a, b, c, ta, tb, tc);
+            tri.n = face.map(|i| p[i]);
+            tri.color = face.map(|i| [i as f64 * 0.2, 0.5, 1.0 - i as f64 * 0.1]);
+            mesh.tris.push(tri);
+        }
+        mesh
+    }
+
+    #[test]
+    fn obj_round_trip() {
+        let mesh = octahedron();
+        let path = std::env::temp_dir().join("engine_3d_round_trip.obj");
+        let path = path.to_str().unwrap();
+        mesh.save_obj(path).unwrap();
+        let options = LoadOptions {
+            has_tex: true,
+            center: true,
+        };
+        let loaded = Mesh::load(path, options).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.tris.len(), mesh.tris.len());
+        for (a, b) in mesh.tris.iter().zip(&loaded.tris) {
+            for i in 0..3 {
+                assert!(length(&(&a.p[i] - &b.p[i])) < 1e-9);
+                assert!(length(&(&a.n[i] - &b.n[i])) < 1e-9);
+                assert!((a.t[i].u - b.t[i].u).abs() < 1e-9);
+                assert!((a.t[i].v - b.t[i].v).abs() < 1e-9);
+                assert_eq!(a.color[i], b.color[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn obj_shares_vertices() {
+        let path = std::env::temp_dir().join("engine_3d_shared.obj");
+        let path = path.to_str().unwrap();
+        octahedron().save_obj(path).unwrap();
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
 
-        Self { tris }
+        let count = |prefix: &str| text.lines().filter(|l| l.starts_with(prefix)).count();
+        assert_eq!(count("v "), 6);
+        assert_eq!(count("vn "), 6);
+        assert_eq!(count("f "), 8);
     }
 }
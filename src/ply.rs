@@ -0,0 +1,388 @@
+use std::fs;
+
+use crate::{
+    mesh::{LoadError, Mesh},
+    triangle::Triangle,
+    vec2d::Vec2D,
+    vec3d::Vec3D,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // What a colour channel of this type reads as at full brightness
+    fn full_scale(self) -> f64 {
+        match self {
+            Scalar::U8 => 255.0,
+            Scalar::U16 => 65535.0,
+            _ => 1.0,
+        }
+    }
+
+    fn decode(self, b: &[u8], big_endian: bool) -> f64 {
+        macro_rules! read {
+            ($t:ty, $n:literal) => {{
+                let bytes: [u8; $n] = b[..$n].try_into().unwrap();
+                if big_endian {
+                    <$t>::from_be_bytes(bytes) as f64
+                } else {
+                    <$t>::from_le_bytes(bytes) as f64
+                }
+            }};
+        }
+        match self {
+            Scalar::I8 => read!(i8, 1),
+            Scalar::U8 => read!(u8, 1),
+            Scalar::I16 => read!(i16, 2),
+            Scalar::U16 => read!(u16, 2),
+            Scalar::I32 => read!(i32, 4),
+            Scalar::U32 => read!(u32, 4),
+            Scalar::F32 => read!(f32, 4),
+            Scalar::F64 => read!(f64, 8),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Property {
+    name: String,
+    kind: Scalar,
+    // Type of the item count, for list properties
+    list: Option<Scalar>,
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// Where the body of the file is read from. ASCII files have one element per
+// line, and `first_line` is the line number of the body's first line in the
+// file, for error messages
+enum Body<'a> {
+    Ascii {
+        lines: std::iter::Enumerate<std::str::Lines<'a>>,
+        first_line: usize,
+    },
+    Binary {
+        bytes: &'a [u8],
+        pos: usize,
+        big_endian: bool,
+    },
+}
+
+impl Body<'_> {
+    // Read one element into `row`. Every property's values are appended in
+    // order, list items without their count, and `starts` gets the index in
+    // `row` where each property begins, plus the end of the row
+    fn read_row(
+        &mut self,
+        properties: &[Property],
+        row: &mut Vec<f64>,
+        starts: &mut Vec<usize>,
+    ) -> Result<(), LoadError> {
+        row.clear();
+        starts.clear();
+
+        match self {
+            Body::Ascii { lines, first_line } => {
+                let (i, text) = lines
+                    .by_ref()
+                    .find(|(_, l)| !l.trim().is_empty())
+                    .ok_or_else(|| LoadError::Format("file ends early".to_string()))?;
+                let error = |message: &str| LoadError::Parse {
+                    line: *first_line + i,
+                    message: message.to_string(),
+                };
+
+                let mut words = text.split_ascii_whitespace();
+                let mut next = || -> Result<f64, LoadError> {
+                    words
+                        .next()
+                        .and_then(|w| w.parse::<f64>().ok())
+                        .ok_or_else(|| error("missing or bad value"))
+                };
+                for property in properties {
+                    starts.push(row.len());
+                    let count = match property.list {
+                        Some(_) => next()? as usize,
+                        None => 1,
+                    };
+                    for _ in 0..count {
+                        row.push(next()?);
+                    }
+                }
+            }
+            Body::Binary {
+                bytes,
+                pos,
+                big_endian,
+            } => {
+                let mut next = |kind: Scalar| -> Result<f64, LoadError> {
+                    let b = bytes
+                        .get(*pos..*pos + kind.size())
+                        .ok_or_else(|| LoadError::Format("file ends early".to_string()))?;
+                    *pos += kind.size();
+                    Ok(kind.decode(b, *big_endian))
+                };
+                for property in properties {
+                    starts.push(row.len());
+                    let count = match property.list {
+                        Some(count_kind) => next(count_kind)? as usize,
+                        None => 1,
+                    };
+                    for _ in 0..count {
+                        row.push(next(property.kind)?);
+                    }
+                }
+            }
+        }
+
+        starts.push(row.len());
+        Ok(())
+    }
+}
+
+// Load a PLY file, in ASCII or either binary byte order. Vertex positions,
+// colours (`red`, `green`, `blue`), normals (`nx`, `ny`, `nz`) and texture
+// coordinates (`s`/`t` or `u`/`v`) are read, and faces with more than three
+// corners are split into fans. Other elements are skipped. Positions are kept
+// as they are in the file
+pub fn load_ply(filename: &str) -> Result<Mesh, LoadError> {
+    let bytes = fs::read(filename)?;
+    let format_error = |message: &str| LoadError::Format(message.to_string());
+
+    // The header is text, ending at the line "end_header"
+    let header_end = bytes
+        .windows(10)
+        .position(|w| w == b"end_header")
+        .ok_or_else(|| format_error("no end_header"))?;
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |i| header_end + i + 1);
+    let header = std::str::from_utf8(&bytes[..header_end])
+        .map_err(|_| format_error("header is not text"))?;
+
+    let mut encoding = None;
+    let mut elements: Vec<Element> = vec![];
+    for (i, line) in header.lines().enumerate() {
+        let error = |message: &str| LoadError::Parse {
+            line: i + 1,
+            message: message.to_string(),
+        };
+        let words = line.split_ascii_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            ["ply"] | [] => {}
+            ["comment", ..] | ["obj_info", ..] => {}
+            ["format", format, _] => {
+                encoding = Some(match format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::LittleEndian,
+                    "binary_big_endian" => Encoding::BigEndian,
+                    _ => return Err(error("unknown format")),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| error("bad element count"))?,
+                properties: vec![],
+            }),
+            ["property", "list", count, kind, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| error("property before any element"))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind: Scalar::parse(kind).ok_or_else(|| error("unknown type"))?,
+                    list: Some(Scalar::parse(count).ok_or_else(|| error("unknown type"))?),
+                });
+            }
+            ["property", kind, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| error("property before any element"))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind: Scalar::parse(kind).ok_or_else(|| error("unknown type"))?,
+                    list: None,
+                });
+            }
+            _ => return Err(error("unrecognised header line")),
+        }
+    }
+
+    let mut body = match encoding.ok_or_else(|| format_error("no format line"))? {
+        Encoding::Ascii => {
+            let text = std::str::from_utf8(&bytes[body_start..])
+                .map_err(|_| format_error("ASCII body is not text"))?;
+            Body::Ascii {
+                lines: text.lines().enumerate(),
+                // The header's lines and then the end_header line
+                first_line: header.lines().count() + 2,
+            }
+        }
+        e => Body::Binary {
+            bytes: &bytes[body_start..],
+            pos: 0,
+            big_endian: e == Encoding::BigEndian,
+        },
+    };
+
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut colors = vec![];
+    let mut tex_coords = vec![];
+    let mut faces = vec![];
+
+    let mut row = vec![];
+    let mut starts = vec![];
+    for element in &elements {
+        let find = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .position(|p| names.contains(&p.name.as_str()))
+        };
+
+        match element.name.as_str() {
+            "vertex" => {
+                let xyz = [find(&["x"]), find(&["y"]), find(&["z"])];
+                let [Some(x), Some(y), Some(z)] = xyz else {
+                    return Err(format_error("vertices have no position"));
+                };
+                let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+                let color = [
+                    find(&["red", "diffuse_red", "r"]),
+                    find(&["green", "diffuse_green", "g"]),
+                    find(&["blue", "diffuse_blue", "b"]),
+                ];
+                let uv = [
+                    find(&["s", "u", "texture_u", "texture_s"]),
+                    find(&["t", "v", "texture_v", "texture_t"]),
+                ];
+
+                for _ in 0..element.count {
+                    body.read_row(&element.properties, &mut row, &mut starts)?;
+                    let value = |i: usize| row[starts[i]];
+                    positions.push(Vec3D::new(value(x), value(y), value(z)));
+                    if let [Some(x), Some(y), Some(z)] = normal {
+                        normals.push(Vec3D::new(value(x), value(y), value(z)));
+                    }
+                    if let [Some(r), Some(g), Some(b)] = color {
+                        colors.push(
+                            [r, g, b].map(|i| value(i) / element.properties[i].kind.full_scale()),
+                        );
+                    }
+                    if let [Some(u), Some(v)] = uv {
+                        tex_coords.push(Vec2D::new(value(u), 1.0 - value(v)));
+                    }
+                }
+            }
+            "face" => {
+                let indices = find(&["vertex_indices", "vertex_index"])
+                    .filter(|&i| element.properties[i].list.is_some())
+                    .ok_or_else(|| format_error("faces have no vertex list"))?;
+                for _ in 0..element.count {
+                    body.read_row(&element.properties, &mut row, &mut starts)?;
+                    // Every value is read as a float, so check the indices
+                    // are whole numbers before using them
+                    let face = row[starts[indices]..starts[indices + 1]]
+                        .iter()
+                        .map(|&i| {
+                            if i >= 0.0 && i.fract() == 0.0 {
+                                Ok(i as usize)
+                            } else {
+                                Err(format_error(&format!(
+                                    "face {} has vertex index {}",
+                                    faces.len(),
+                                    i
+                                )))
+                            }
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    faces.push(face);
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    body.read_row(&element.properties, &mut row, &mut starts)?;
+                }
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new(vec![]);
+    for (f, face) in faces.iter().enumerate() {
+        if let Some(&i) = face.iter().find(|&&i| i >= positions.len()) {
+            return Err(format_error(&format!(
+                "face {} uses vertex {} of {}",
+                f,
+                i,
+                positions.len()
+            )));
+        }
+
+        for k in 1..face.len().saturating_sub(1) {
+            let corners = [face[0], face[k], face[k + 1]];
+            let [a, b, c] = corners.map(|i| positions[i]);
+            let mut tri = if tex_coords.is_empty() {
+                Triangle::new(a, b, c)
+            } else {
+                let [ta, tb, tc] = corners.map(|i| tex_coords[i]);
+                Triangle::new_uv(a, b, c, ta, tb, tc)
+            };
+            if !normals.is_empty() {
+                tri.n = corners.map(|i| normals[i]);
+            }
+            if !colors.is_empty() {
+                tri.color = corners.map(|i| colors[i]);
+            }
+            mesh.tris.push(tri);
+        }
+    }
+
+    Ok(mesh)
+}
@@ -0,0 +1,149 @@
+use crate::{
+    mat4x4::{inverse, multiply_vector, Mat4x4},
+    mesh::Mesh,
+    triangle::Triangle,
+    vec3d::{cross_product, dot_product, intersect_plane, Vec3D},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vec3D,
+    pub dir: Vec3D,
+}
+
+// Result of a ray hitting a mesh. `t` is the distance along the ray, and
+// `barycentric` holds the weights of the triangle's three vertices at `point`
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    pub index: usize,
+    pub t: f64,
+    pub point: Vec3D,
+    pub barycentric: [f64; 3],
+}
+
+impl Ray {
+    pub fn new(origin: Vec3D, dir: Vec3D) -> Self {
+        Self {
+            origin,
+            dir: dir.normalise(),
+        }
+    }
+
+    pub fn at(&self, t: f64) -> Vec3D {
+        let p = &self.origin + &(&self.dir * t);
+        Vec3D::new(p.x, p.y, p.z)
+    }
+
+    // Transform the ray by a matrix, e.g. to bring a world space ray into the
+    // local space of a mesh. Distances along the new ray are only comparable
+    // to the old ones if `m` does not scale
+    pub fn transform(&self, m: &Mat4x4) -> Ray {
+        let origin = multiply_vector(m, &self.origin);
+        let end = multiply_vector(m, &self.at(1.0));
+        Ray::new(origin, &end - &origin)
+    }
+}
+
+// Turn a pixel position into a world space ray through the view and projection
+// matrices. This undoes the viewer's mapping from normalised device coordinates
+// to the screen, where x and y are flipped and then scaled to the canvas
+pub fn screen_to_ray(
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    mat_proj: &Mat4x4,
+    mat_view: &Mat4x4,
+) -> Option<Ray> {
+    // Aim through the centre of the pixel
+    let ndc_x = 1.0 - 2.0 * (x + 0.5) / width;
+    let ndc_y = 1.0 - 2.0 * (y + 0.5) / height;
+
+    let inv_proj = inverse(mat_proj)?;
+    let inv_view = inverse(mat_view)?;
+
+    // Unproject points on the near and far planes back into view space
+    let unproject = |z: f64| -> Vec3D {
+        let p = multiply_vector(&inv_proj, &Vec3D::new(ndc_x, ndc_y, z));
+        let p = &p / p.w;
+        multiply_vector(&inv_view, &Vec3D::new(p.x, p.y, p.z))
+    };
+    let near = unproject(0.0);
+    let far = unproject(1.0);
+
+    Some(Ray::new(near, &far - &near))
+}
+
+// Intersect a ray with a single triangle from either side. The ray is first
+// intersected with the triangle's plane, then the hit point is tested against
+// the triangle's edges. Returns the distance along the ray and the barycentric
+// coordinates of the hit
+pub fn intersect_triangle(ray: &Ray, tri: &Triangle) -> Option<(f64, [f64; 3])> {
+    let line1 = &tri.p[1] - &tri.p[0];
+    let line2 = &tri.p[2] - &tri.p[0];
+    let normal = cross_product(&line1, &line2);
+    let area = dot_product(&normal, &normal);
+
+    // Degenerate triangle, or ray parallel to its plane
+    if area < 1e-12 || dot_product(&normal, &ray.dir).abs() < 1e-12 {
+        return None;
+    }
+
+    let mut t = 0.0;
+    let point = intersect_plane(&tri.p[0], &normal, &ray.origin, &ray.at(1.0), &mut t);
+    if t < 0.0 {
+        return None;
+    }
+
+    // Each barycentric weight is the area of the sub triangle opposite its
+    // vertex, signed so it goes negative when the point is outside that edge
+    let weight = |a: &Vec3D, b: &Vec3D| -> f64 {
+        let edge = b - a;
+        let to_point = &point - a;
+        dot_product(&cross_product(&edge, &to_point), &normal) / area
+    };
+    let b0 = weight(&tri.p[1], &tri.p[2]);
+    let b1 = weight(&tri.p[2], &tri.p[0]);
+    let b2 = 1.0 - b0 - b1;
+
+    if b0 < 0.0 || b1 < 0.0 || b2 < 0.0 {
+        return None;
+    }
+
+    Some((t, [b0, b1, b2]))
+}
+
+// Intersect a ray with a triangle of a mesh, skipping the faces the triangle's
+// cull mode skips when rendering
+pub(crate) fn pick_triangle(ray: &Ray, index: usize, tri: &Triangle) -> Option<Hit> {
+    let line1 = &tri.p[1] - &tri.p[0];
+    let line2 = &tri.p[2] - &tri.p[0];
+    let front_facing = dot_product(&cross_product(&line1, &line2), &ray.dir) < 0.0;
+    if !tri.cull.keeps(front_facing) {
+        return None;
+    }
+
+    intersect_triangle(ray, tri).map(|(t, barycentric)| Hit {
+        index,
+        t,
+        point: ray.at(t),
+        barycentric,
+    })
+}
+
+// Find the closest triangle of a mesh hit by the ray by testing every
+// triangle, skipping the faces each triangle culls. `Bvh::pick` gives the same
+// result much faster on big meshes
+pub fn pick(ray: &Ray, mesh: &Mesh) -> Option<Hit> {
+    let mut closest: Option<Hit> = None;
+
+    for (index, tri) in mesh.tris.iter().enumerate() {
+        if let Some(hit) = pick_triangle(ray, index, tri) {
+            if closest.is_none_or(|c| hit.t < c.t) {
+                closest = Some(hit);
+            }
+        }
+    }
+
+    closest
+}
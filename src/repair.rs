@@ -0,0 +1,348 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+use crate::{
+    mesh::Mesh,
+    vec3d::{cross_product, dot_product, length, Vec3D},
+};
+
+// Problems found in a mesh. Positions closer together than the epsilon the
+// mesh was checked with count as the same vertex
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MeshReport {
+    pub triangles: usize,
+    pub vertices: usize,
+    // Positions that are within epsilon of another but not exactly equal to it
+    pub duplicate_vertices: usize,
+    // Triangles with two corners together, or all three in a line
+    pub degenerate_triangles: usize,
+    // Edges used by only one triangle, which are the edges of holes or of an
+    // open surface
+    pub boundary_edges: usize,
+    // Edges shared by more than two triangles
+    pub non_manifold_edges: usize,
+    // Edges whose two triangles run along them the same way, so one of them is
+    // wound backwards
+    pub flipped_edges: usize,
+}
+
+impl MeshReport {
+    // Whether the mesh is a closed surface with every face wound the same way
+    pub fn is_clean(&self) -> bool {
+        self.duplicate_vertices == 0
+            && self.degenerate_triangles == 0
+            && self.boundary_edges == 0
+            && self.non_manifold_edges == 0
+            && self.flipped_edges == 0
+    }
+}
+
+impl fmt::Display for MeshReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} tris, {} verts, {} duplicate verts, {} degenerate, {} boundary edges, {} non-manifold edges, {} flipped edges",
+            self.triangles,
+            self.vertices,
+            self.duplicate_vertices,
+            self.degenerate_triangles,
+            self.boundary_edges,
+            self.non_manifold_edges,
+            self.flipped_edges
+        )
+    }
+}
+
+// The mesh's corners welded into shared vertices
+struct Welded {
+    positions: Vec<Vec3D>,
+    tris: Vec<[usize; 3]>,
+    // Positions that were merged into one within epsilon, rather than equal
+    merged: usize,
+}
+
+// Weld every corner to the first position within `epsilon` of it. Positions
+// are bucketed in a grid of `epsilon` sized cells so only nearby cells need
+// searching
+fn weld(mesh: &Mesh, epsilon: f64) -> Welded {
+    let mut positions: Vec<Vec3D> = vec![];
+    let mut exact = HashMap::new();
+    let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+    let mut merged = 0;
+    let cell = |p: &Vec3D| [p.x, p.y, p.z].map(|c| (c / epsilon).floor() as i64);
+
+    let mut find = |p: &Vec3D| -> usize {
+        // Adding 0.0 turns -0.0 into 0.0
+        let bits = [p.x, p.y, p.z].map(|c| (c + 0.0).to_bits());
+        if let Some(&i) = exact.get(&bits) {
+            return i;
+        }
+
+        let found = if epsilon > 0.0 {
+            let [x, y, z] = cell(p);
+            let mut near = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        for &i in grid.get(&[x + dx, y + dy, z + dz]).into_iter().flatten() {
+                            if length(&(&positions[i] - p)) <= epsilon {
+                                near = Some(i);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+            near
+        } else {
+            None
+        };
+
+        let i = match found {
+            Some(i) => {
+                merged += 1;
+                i
+            }
+            None => {
+                positions.push(*p);
+                if epsilon > 0.0 {
+                    grid.entry(cell(p)).or_default().push(positions.len() - 1);
+                }
+                positions.len() - 1
+            }
+        };
+        exact.insert(bits, i);
+        i
+    };
+
+    let tris = mesh
+        .tris
+        .iter()
+        .map(|tri| [find(&tri.p[0]), find(&tri.p[1]), find(&tri.p[2])])
+        .collect();
+    Welded {
+        positions,
+        tris,
+        merged,
+    }
+}
+
+// A triangle is degenerate if two of its welded corners are the same, or if it
+// is thinner than `epsilon` across its longest side
+fn is_degenerate(positions: &[Vec3D], [a, b, c]: [usize; 3], epsilon: f64) -> bool {
+    if a == b || b == c || c == a {
+        return true;
+    }
+    let [pa, pb, pc] = [a, b, c].map(|i| positions[i]);
+    let area2 = length(&cross_product(&(&pb - &pa), &(&pc - &pa)));
+    let longest = [(&pb - &pa), (&pc - &pb), (&pa - &pc)]
+        .iter()
+        .map(length)
+        .fold(0.0, f64::max);
+    area2 <= epsilon * longest
+}
+
+// For each edge, the triangles using it and whether each runs from the lower
+// numbered vertex to the higher
+fn edges(tris: &[[usize; 3]], skip: &[bool]) -> HashMap<(usize, usize), Vec<(usize, bool)>> {
+    let mut edges: HashMap<_, Vec<_>> = HashMap::new();
+    for (i, tri) in tris.iter().enumerate() {
+        if skip[i] {
+            continue;
+        }
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            edges
+                .entry((a.min(b), a.max(b)))
+                .or_default()
+                .push((i, a < b));
+        }
+    }
+    edges
+}
+
+pub fn analyze(mesh: &Mesh, epsilon: f64) -> MeshReport {
+    let welded = weld(mesh, epsilon);
+    let degenerate = welded
+        .tris
+        .iter()
+        .map(|&tri| is_degenerate(&welded.positions, tri, epsilon))
+        .collect::<Vec<_>>();
+
+    let mut report = MeshReport {
+        triangles: mesh.tris.len(),
+        vertices: welded.positions.len(),
+        duplicate_vertices: welded.merged,
+        degenerate_triangles: degenerate.iter().filter(|&&d| d).count(),
+        ..MeshReport::default()
+    };
+    for uses in edges(&welded.tris, &degenerate).values() {
+        match uses[..] {
+            [_] => report.boundary_edges += 1,
+            [(_, a), (_, b)] if a == b => report.flipped_edges += 1,
+            [_, _] => {}
+            _ => report.non_manifold_edges += 1,
+        }
+    }
+    report
+}
+
+// Move corners within `epsilon` of each other onto the same position, so the
+// triangles around them share it exactly. Returns how many positions were
+// merged into others
+pub fn weld_vertices(mesh: &mut Mesh, epsilon: f64) -> usize {
+    let welded = weld(mesh, epsilon);
+    for (tri, corners) in mesh.tris.iter_mut().zip(&welded.tris) {
+        for (corner, &i) in tri.p.iter_mut().zip(corners) {
+            let p = welded.positions[i];
+            corner.x = p.x;
+            corner.y = p.y;
+            corner.z = p.z;
+        }
+    }
+    welded.merged
+}
+
+// Returns how many triangles were removed
+pub fn remove_degenerates(mesh: &mut Mesh, epsilon: f64) -> usize {
+    let welded = weld(mesh, epsilon);
+    let before = mesh.tris.len();
+    mesh.tris = mesh
+        .tris
+        .iter()
+        .zip(&welded.tris)
+        .filter(|(_, &corners)| !is_degenerate(&welded.positions, corners, epsilon))
+        .map(|(tri, _)| *tri)
+        .collect();
+    before - mesh.tris.len()
+}
+
+// Flip triangles so that neighbours across each edge agree on which way they
+// face. Each connected piece is made to agree with the winding most of its
+// triangles already had, except closed pieces, which are turned so they face
+// outwards. Pieces only connect across edges shared by exactly two triangles.
+// Returns how many triangles were flipped
+pub fn unify_winding(mesh: &mut Mesh, epsilon: f64) -> usize {
+    let welded = weld(mesh, epsilon);
+    let degenerate = welded
+        .tris
+        .iter()
+        .map(|&tri| is_degenerate(&welded.positions, tri, epsilon))
+        .collect::<Vec<_>>();
+    let edges = edges(&welded.tris, &degenerate);
+
+    // Neighbours of each triangle, and whether they currently run along the
+    // shared edge the same way, meaning one of the two must flip
+    let mut neighbours = vec![vec![]; welded.tris.len()];
+    let mut open = vec![false; welded.tris.len()];
+    for uses in edges.values() {
+        match uses[..] {
+            [(a, dir_a), (b, dir_b)] => {
+                neighbours[a].push((b, dir_a == dir_b));
+                neighbours[b].push((a, dir_a == dir_b));
+            }
+            _ => uses.iter().for_each(|&(t, _)| open[t] = true),
+        }
+    }
+
+    let mut flip = vec![false; welded.tris.len()];
+    let mut seen = degenerate.clone();
+    let mut flipped = 0;
+    for start in 0..welded.tris.len() {
+        if seen[start] {
+            continue;
+        }
+
+        // Walk the piece, deciding each triangle's flip from the one it was
+        // reached from
+        let mut piece = vec![start];
+        let mut queue = VecDeque::from([start]);
+        seen[start] = true;
+        while let Some(t) = queue.pop_front() {
+            for &(n, opposed) in &neighbours[t] {
+                if !seen[n] {
+                    seen[n] = true;
+                    flip[n] = flip[t] ^ opposed;
+                    piece.push(n);
+                    queue.push_back(n);
+                }
+            }
+        }
+
+        let turn_over = if piece.iter().any(|&t| open[t]) {
+            piece.iter().filter(|&&t| flip[t]).count() * 2 > piece.len()
+        } else {
+            // Signed volume, which is negative if the faces point inwards
+            let volume = piece
+                .iter()
+                .map(|&t| {
+                    let [a, b, c] = welded.tris[t].map(|i| welded.positions[i]);
+                    let s = if flip[t] { -1.0 } else { 1.0 };
+                    s * dot_product(&a, &cross_product(&b, &c))
+                })
+                .sum::<f64>();
+            volume < 0.0
+        };
+        for &t in &piece {
+            if flip[t] != turn_over {
+                mesh.tris[t].flip();
+                flipped += 1;
+            }
+        }
+    }
+    flipped
+}
+
+// Set the normals from the faces. Corners that share a position are smoothed
+// together with the faces around it that are within `crease_angle` degrees of
+// their own, weighted by area, so 0.0 gives flat shading and 180.0 smooths
+// everything
+pub fn recompute_normals(mesh: &mut Mesh, crease_angle: f64) {
+    let welded = weld(mesh, 0.0);
+    let faces = mesh
+        .tris
+        .iter()
+        .map(|tri| cross_product(&(&tri.p[1] - &tri.p[0]), &(&tri.p[2] - &tri.p[0])))
+        .collect::<Vec<_>>();
+
+    let mut around = vec![vec![]; welded.positions.len()];
+    for (t, corners) in welded.tris.iter().enumerate() {
+        for &p in corners {
+            around[p].push(t);
+        }
+    }
+
+    // Faces with no area have no direction, and are left as zero
+    let unit = |v: &Vec3D| if length(v) > 0.0 { v.normalise() } else { *v };
+    let min_cos = crease_angle.to_radians().cos();
+    for (t, corners) in welded.tris.iter().enumerate() {
+        let own = unit(&faces[t]);
+        for k in 0..3 {
+            let mut sum = Vec3D::empty();
+            for &other in &around[corners[k]] {
+                let face = &faces[other];
+                if other == t || dot_product(&own, &unit(face)) >= min_cos - 1e-9 {
+                    sum = &sum + face;
+                }
+            }
+            mesh.tris[t].n[k] = if length(&sum) > 0.0 {
+                sum.normalise()
+            } else {
+                own
+            };
+        }
+    }
+}
+
+// Weld, remove degenerate triangles, unify the winding and recompute the
+// normals, in that order. Returns the report from before and after
+pub fn repair(mesh: &mut Mesh, epsilon: f64, crease_angle: f64) -> (MeshReport, MeshReport) {
+    let before = analyze(mesh, epsilon);
+    weld_vertices(mesh, epsilon);
+    remove_degenerates(mesh, epsilon);
+    unify_winding(mesh, epsilon);
+    recompute_normals(mesh, crease_angle);
+    (before, analyze(mesh, epsilon))
+}
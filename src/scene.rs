@@ -0,0 +1,78 @@
+use image::DynamicImage;
+
+use crate::{
+    mat4x4::{make_identity, multiply_matrix, Mat4x4},
+    mesh::Mesh,
+};
+
+// A point in a scene's hierarchy. Its transform places it relative to its
+// parent, and `meshes` are indices into the scene's meshes drawn at the node
+pub struct Node {
+    pub name: Option<String>,
+    pub transform: Mat4x4,
+    pub meshes: Vec<usize>,
+    pub children: Vec<usize>,
+}
+
+// Meshes placed by a hierarchy of nodes, along with the textures their
+// materials refer to by index
+pub struct Scene {
+    pub meshes: Vec<Mesh>,
+    pub textures: Vec<DynamicImage>,
+    pub nodes: Vec<Node>,
+    pub roots: Vec<usize>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self {
+            meshes: vec![],
+            textures: vec![],
+            nodes: vec![],
+            roots: vec![],
+        }
+    }
+
+    // Every mesh drawn by the scene with the world matrix it is drawn with.
+    // A mesh used by several nodes appears once for each
+    pub fn instances(&self) -> Vec<(usize, Mat4x4)> {
+        let mut instances = vec![];
+        let mut stack = self
+            .roots
+            .iter()
+            .map(|&root| (root, make_identity()))
+            .collect::<Vec<_>>();
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            let world = multiply_matrix(&node.transform, &parent);
+            instances.extend(node.meshes.iter().map(|&mesh| (mesh, world)));
+            stack.extend(node.children.iter().map(|&child| (child, world)));
+        }
+        instances
+    }
+
+    // All of the scene's meshes moved into world space with `Mesh::transform`
+    // and merged into one mesh, which takes the material of the first. Each
+    // triangle keeps the cull mode of its own mesh's material
+    pub fn flatten(&self) -> Mesh {
+        let mut flat = Mesh::new(vec![]);
+        let instances = self.instances();
+        if let Some(&(first, _)) = instances.first() {
+            flat.material = self.meshes[first].material;
+        }
+
+        for (index, mat_world) in instances {
+            let mut mesh = Mesh::new(vec![]);
+            mesh.tris = self.meshes[index].tris.clone();
+            mesh.transform(&mat_world);
+            flat.tris.append(&mut mesh.tris);
+        }
+        flat
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    aabb::Aabb,
+    clip::clip_triangle,
+    mat4x4::{
+        make_look_at, make_orthographic, multiply_matrix, multiply_vector, Handedness, Mat4x4,
+    },
+    scan_triangle,
+    triangle::Triangle,
+    vec3d::{cross_product, length, Vec3D},
+};
+
+// Depth of the scene as seen from a directional light. Surfaces further from
+// the light than the depth stored for their texel are in shadow
+pub struct ShadowMap {
+    pub size: usize,
+    pub depth: Vec<f64>,
+    // World space --> light clip space
+    pub mat_light: Mat4x4,
+    // Distance in world units a surface must be behind the stored depth to be
+    // shadowed, which stops surfaces shadowing themselves
+    pub bias: f64,
+    // Radius in texels of the percentage closer filter used to soften shadow
+    // edges. 0 gives hard shadows
+    pub pcf: usize,
+    depth_range: f64,
+}
+
+impl ShadowMap {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            depth: vec![1.0; size * size],
+            mat_light: Mat4x4::default(),
+            bias: 0.5,
+            pcf: 0,
+            depth_range: 1.0,
+        }
+    }
+
+    // Render world space triangles into the map for a directional light shining
+    // along `direction`. The light's orthographic projection is fitted around
+    // `bounds`, which should hold every triangle that can cast or receive
+    // shadows
+    pub fn render(&mut self, tris: &[Triangle], direction: &Vec3D, bounds: &Aabb) {
+        let (center, radius) = bounds.bounding_sphere();
+        let direction = direction.normalise();
+
+        // Any up vector will do as long as it is not parallel to the light
+        let up = Vec3D::new(0.0, 1.0, 0.0);
+        let up = if length(&cross_product(&direction, &up)) < 1e-6 {
+            Vec3D::new(0.0, 0.0, 1.0)
+        } else {
+            up
+        };
+
+        let eye = &center - &(&direction * radius);
+        let mat_view = make_look_at(&eye, &center, &up, Handedness::Left);
+        let mat_proj = make_orthographic(
+            -radius,
+            radius,
+            -radius,
+            radius,
+            0.0,
+            2.0 * radius,
+            Handedness::Left,
+        );
+        self.mat_light = multiply_matrix(&mat_view, &mat_proj);
+        self.depth_range = 2.0 * radius;
+
+        self.depth.fill(1.0);
+        let size = self.size as i32;
+        for tri in tris {
+            let mut tri_clip = *tri;
+            for p in &mut tri_clip.p {
+                *p = multiply_vector(&self.mat_light, p);
+            }
+
+            // Both sides of every triangle are drawn, so open meshes still
+            // cast shadows
+            for clipped in clip_triangle(&tri_clip) {
+                let mut tri_map = clipped;
+                for p in &mut tri_map.p {
+                    *p = self.to_map(p);
+                }
+                scan_triangle(size, size, &tri_map, &mut self.depth, |_, _, _| {});
+            }
+        }
+    }
+
+    // Light clip space --> texel coordinates and depth
+    fn to_map(&self, p: &Vec3D) -> Vec3D {
+        let size = self.size as f64;
+        Vec3D::new(
+            (p.x / p.w + 1.0) * 0.5 * size,
+            (1.0 - p.y / p.w) * 0.5 * size,
+            p.z / p.w,
+        )
+    }
+
+    // How much of the light reaches a world space point, from 0.0 for fully
+    // shadowed to 1.0 for fully lit
+    pub fn visibility(&self, world: &Vec3D) -> f64 {
+        let p = self.to_map(&multiply_vector(&self.mat_light, world));
+        let depth = p.z - self.bias / self.depth_range;
+
+        let x = p.x as i64;
+        let y = p.y as i64;
+        let r = self.pcf as i64;
+        let size = self.size as i64;
+
+        let mut lit = 0;
+        let mut total = 0;
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let (sx, sy) = (x + dx, y + dy);
+                total += 1;
+                // Outside the map nothing was rendered, so it is lit
+                if sx < 0 || sy < 0 || sx >= size || sy >= size {
+                    lit += 1;
+                    continue;
+                }
+                if depth <= self.depth[(sy * size + sx) as usize] {
+                    lit += 1;
+                }
+            }
+        }
+
+        lit as f64 / total as f64
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    collections::HashMap,
+    f64::consts::{PI, TAU},
+};
+
+use crate::{
+    mesh::Mesh,
+    terrain::Heightmap,
+    triangle::Triangle,
+    vec2d::Vec2D,
+    vec3d::{dot_product, length, Vec3D},
+};
+
+// Meshes built in code. Every shape is centered on the origin with y up and
+// has smooth normals where its surface is curved. Texture coordinates run from
+// 0.0 to 1.0 with v = 0.0 at the top, like the OBJ loader produces
+
+// Collects shared vertices and turns them into triangles. Each triangle is
+// wound to agree with its vertex normals, so the generators don't need to get
+// the order of every face right, and zero-area triangles (such as those at the
+// poles of a sphere) are left out
+struct Builder {
+    verts: Vec<(Vec3D, Vec3D, Vec2D)>,
+    tris: Vec<Triangle>,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Self {
+            verts: vec![],
+            tris: vec![],
+        }
+    }
+
+    fn vertex(&mut self, p: Vec3D, n: Vec3D, u: f64, v: f64) -> usize {
+        self.verts.push((p, n, Vec2D::new(u, v)));
+        self.verts.len() - 1
+    }
+
+    fn triangle(&mut self, a: usize, b: usize, c: usize) {
+        let [(pa, na, ta), (pb, nb, tb), (pc, nc, tc)] = [a, b, c].map(|i| self.verts[i]);
+        let mut tri = Triangle::new_uv(pa, pb, pc, ta, tb, tc);
+        let face = tri.normal();
+        if length(&face) == 0.0 {
+            return;
+        }
+        tri.n = [na, nb, nc];
+        if dot_product(&face, &(&(&na + &nb) + &nc)) < 0.0 {
+            tri.p.swap(1, 2);
+            tri.t.swap(1, 2);
+            tri.n.swap(1, 2);
+        }
+        self.tris.push(tri);
+    }
+
+    // Two triangles for the corners of a quad, in order around its edge
+    fn quad(&mut self, a: usize, b: usize, c: usize, d: usize) {
+        self.triangle(a, b, c);
+        self.triangle(a, c, d);
+    }
+
+    // Quads between a grid of vertices laid out row by row, `columns` wide
+    fn grid(&mut self, first: usize, columns: usize, rows: usize) {
+        for r in 0..rows - 1 {
+            for c in 0..columns - 1 {
+                let i = first + r * columns + c;
+                self.quad(i, i + 1, i + columns + 1, i + columns);
+            }
+        }
+    }
+
+    fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(vec![]);
+        mesh.tris = self.tris;
+        mesh
+    }
+}
+
+// A cube with sides of length `size`. Each face has its own vertices, so the
+// edges stay sharp, and shows the whole texture
+pub fn cube(size: f64) -> Mesh {
+    let h = size / 2.0;
+    let mut b = Builder::new();
+    let axes = [
+        Vec3D::new(1.0, 0.0, 0.0),
+        Vec3D::new(0.0, 1.0, 0.0),
+        Vec3D::new(0.0, 0.0, 1.0),
+    ];
+    for (i, axis) in axes.iter().enumerate() {
+        // Two directions across the face, to place its corners
+        let s = &axes[(i + 1) % 3];
+        let t = &axes[(i + 2) % 3];
+        for sign in [1.0, -1.0] {
+            let n = axis * sign;
+            let center = &n * h;
+            let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(a, c)| {
+                let p = &(&center + &(s * (a * h))) + &(t * (c * h));
+                b.vertex(p, n, (a + 1.0) / 2.0, (1.0 - c) / 2.0)
+            });
+            b.quad(corners[0], corners[1], corners[2], corners[3]);
+        }
+    }
+    b.build()
+}
+
+// A sphere made of `rings` bands from pole to pole, each split into
+// `segments` around the y axis. The texture wraps around once, with u
+// following the longitude and v the latitude
+pub fn uv_sphere(radius: f64, segments: usize, rings: usize) -> Mesh {
+    let segments = segments.max(3);
+    let rings = rings.max(2);
+    let mut b = Builder::new();
+    for i in 0..=rings {
+        let v = i as f64 / rings as f64;
+        let theta = v * PI;
+        // Exactly zero at the poles, so their triangles are dropped
+        let sin_theta = if i == 0 || i == rings {
+            0.0
+        } else {
+            theta.sin()
+        };
+        for j in 0..=segments {
+            let u = j as f64 / segments as f64;
+            let (sin_phi, cos_phi) = turn(j, segments);
+            let n = Vec3D::new(sin_theta * cos_phi, theta.cos(), sin_theta * sin_phi);
+            b.vertex(&n * radius, n, u, v);
+        }
+    }
+    b.grid(0, segments + 1, rings + 1);
+    b.build()
+}
+
+// A sphere made by splitting each face of an icosahedron into four,
+// `subdivisions` times, which spreads the triangles more evenly than
+// `uv_sphere`. Texture coordinates use the same mapping as `uv_sphere`
+pub fn icosphere(radius: f64, subdivisions: usize) -> Mesh {
+    let g = (1.0 + 5.0_f64.sqrt()) / 2.0;
+    let mut points = [
+        (-1.0, g, 0.0),
+        (1.0, g, 0.0),
+        (-1.0, -g, 0.0),
+        (1.0, -g, 0.0),
+        (0.0, -1.0, g),
+        (0.0, 1.0, g),
+        (0.0, -1.0, -g),
+        (0.0, 1.0, -g),
+        (g, 0.0, -1.0),
+        (g, 0.0, 1.0),
+        (-g, 0.0, -1.0),
+        (-g, 0.0, 1.0),
+    ]
+    .map(|(x, y, z)| Vec3D::new(x, y, z).normalise())
+    .to_vec();
+    let mut faces = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // Edges shared by two faces get one midpoint between them
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: usize, b: usize, points: &mut Vec<Vec3D>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push((&(&points[a] + &points[b]) * 0.5).normalise());
+                points.len() - 1
+            })
+        };
+        faces = faces
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = midpoint(a, b, &mut points);
+                let bc = midpoint(b, c, &mut points);
+                let ca = midpoint(c, a, &mut points);
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let mut b = Builder::new();
+    for face in faces {
+        let mut uvs = face.map(|i| {
+            let n = &points[i];
+            (
+                n.z.atan2(n.x).rem_euclid(TAU) / TAU,
+                n.y.clamp(-1.0, 1.0).acos() / PI,
+            )
+        });
+        // Faces crossing the seam where u wraps from 1.0 back to 0.0 would
+        // otherwise show the whole texture squeezed in reverse. Their u goes a
+        // little past 1.0 instead
+        let max_u = uvs.iter().map(|uv| uv.0).fold(0.0, f64::max);
+        for uv in &mut uvs {
+            if max_u - uv.0 > 0.5 {
+                uv.0 += 1.0;
+            }
+        }
+        let [a, bb, c] = [0, 1, 2].map(|k| {
+            let n = points[face[k]];
+            b.vertex(&n * radius, n, uvs[k].0, uvs[k].1)
+        });
+        b.triangle(a, bb, c);
+    }
+    b.build()
+}
+
+// A flat grid in the xz plane facing up, split into `x_segments` by
+// `z_segments` quads. The texture covers it once
+pub fn plane(width: f64, depth: f64, x_segments: usize, z_segments: usize) -> Mesh {
+    let (xs, zs) = (x_segments.max(1), z_segments.max(1));
+    let mut b = Builder::new();
+    let up = Vec3D::new(0.0, 1.0, 0.0);
+    for j in 0..=zs {
+        let v = j as f64 / zs as f64;
+        for i in 0..=xs {
+            let u = i as f64 / xs as f64;
+            let p = Vec3D::new((u - 0.5) * width, 0.0, (v - 0.5) * depth);
+            b.vertex(p, up, u, v);
+        }
+    }
+    b.grid(0, xs + 1, zs + 1);
+    b.build()
+}
+
+// A cylinder around the y axis, capped at both ends. The texture wraps around
+// the side once, and each cap shows a circle cut from the middle of it
+pub fn cylinder(radius: f64, height: f64, segments: usize) -> Mesh {
+    let segments = segments.max(3);
+    let h = height / 2.0;
+    let mut b = Builder::new();
+
+    let side = b.verts.len();
+    for (y, v) in [(h, 0.0), (-h, 1.0)] {
+        for j in 0..=segments {
+            let u = j as f64 / segments as f64;
+            let (sin, cos) = turn(j, segments);
+            let n = Vec3D::new(cos, 0.0, sin);
+            let p = Vec3D::new(n.x * radius, y, n.z * radius);
+            b.vertex(p, n, u, v);
+        }
+    }
+    b.grid(side, segments + 1, 2);
+
+    cap(&mut b, radius, h, segments, 1.0);
+    cap(&mut b, radius, -h, segments, -1.0);
+    b.build()
+}
+
+// A cone around the y axis with its point at the top and a capped base
+pub fn cone(radius: f64, height: f64, segments: usize) -> Mesh {
+    let segments = segments.max(3);
+    let h = height / 2.0;
+    let mut b = Builder::new();
+
+    // The slope of the side tips its normals up by the same angle. The point
+    // gets a vertex per segment so each can have the normal of its own segment
+    let normal =
+        |(sin, cos): (f64, f64)| Vec3D::new(cos * height, radius, sin * height).normalise();
+    for j in 0..segments {
+        let corner = |b: &mut Builder, j: usize| {
+            let (sin, cos) = turn(j, segments);
+            let p = Vec3D::new(cos * radius, -h, sin * radius);
+            b.vertex(p, normal((sin, cos)), j as f64 / segments as f64, 1.0)
+        };
+        let c0 = corner(&mut b, j);
+        let c1 = corner(&mut b, j + 1);
+        let mid = (j * 2 + 1) as f64 / (segments * 2) as f64;
+        let tip = b.vertex(
+            Vec3D::new(0.0, h, 0.0),
+            normal((mid * TAU).sin_cos()),
+            mid,
+            0.0,
+        );
+        b.triangle(c0, c1, tip);
+    }
+
+    cap(&mut b, radius, -h, segments, -1.0);
+    b.build()
+}
+
+// A ring around the y axis. `major_radius` is from the center to the middle
+// of the tube and `minor_radius` is the tube's own radius. u runs around the
+// ring and v around the tube
+pub fn torus(major_radius: f64, minor_radius: f64, segments: usize, sides: usize) -> Mesh {
+    let (segments, sides) = (segments.max(3), sides.max(3));
+    let mut b = Builder::new();
+    for j in 0..=sides {
+        let v = j as f64 / sides as f64;
+        let (sin_v, cos_v) = turn(j, sides);
+        for i in 0..=segments {
+            let u = i as f64 / segments as f64;
+            let (sin_u, cos_u) = turn(i, segments);
+            let n = Vec3D::new(cos_v * cos_u, sin_v, cos_v * sin_u);
+            let r = major_radius + minor_radius * cos_v;
+            let p = Vec3D::new(r * cos_u, minor_radius * sin_v, r * sin_u);
+            b.vertex(p, n, u, v);
+        }
+    }
+    b.grid(0, segments + 1, sides + 1);
+    b.build()
+}
+
+// A mesh of a heightmap at full detail, one vertex per sample, with normals
+// following the slope between neighbouring samples. The texture covers the
+// whole grid
+pub fn heightmap(map: &Heightmap) -> Mesh {
+    let mut b = Builder::new();
+    if map.columns < 2 || map.rows < 2 {
+        return b.build();
+    }
+
+    for r in 0..map.rows {
+        for c in 0..map.columns {
+            let u = c as f64 / (map.columns - 1) as f64;
+            let v = r as f64 / (map.rows - 1) as f64;
+            b.vertex(map.position(c, r), map.normal(c, r), u, v);
+        }
+    }
+    b.grid(0, map.columns, map.rows);
+    b.build()
+}
+
+// A flat disc closing the end of a cylinder or cone at height `y`, facing up
+// or down depending on `facing`
+fn cap(b: &mut Builder, radius: f64, y: f64, segments: usize, facing: f64) {
+    let n = Vec3D::new(0.0, facing, 0.0);
+    let center = b.vertex(Vec3D::new(0.0, y, 0.0), n, 0.5, 0.5);
+    let first = b.verts.len();
+    for j in 0..=segments {
+        let (sin, cos) = turn(j, segments);
+        let p = Vec3D::new(cos * radius, y, sin * radius);
+        b.vertex(p, n, 0.5 + cos * 0.5, 0.5 - sin * 0.5);
+    }
+    for j in 0..segments {
+        b.triangle(center, first + j, first + j + 1);
+    }
+}
+
+// Sine and cosine of `i` steps of `n` around a circle. The last step lands
+// exactly on the first, so the vertices either side of a seam match
+fn turn(i: usize, n: usize) -> (f64, f64) {
+    ((i % n) as f64 / n as f64 * TAU).sin_cos()
+}
@@ -0,0 +1,476 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use crate::{
+    mesh::Mesh,
+    triangle::Triangle,
+    vec2d::Vec2D,
+    vec3d::{cross_product, dot_product, length, Vec3D},
+};
+
+// How much more it costs to move a border or seam edge away from where it was
+// than to move a surface the same distance
+const SEAM_WEIGHT: f64 = 1000.0;
+
+// A collapse is refused if it would turn any triangle further than this from
+// the way it faced in the original mesh, as the cosine of the angle. Checking
+// against the original rather than the previous step stops small turns adding
+// up until faces end up inside out
+const MIN_FACING: f64 = 0.2;
+
+// Sum of squared distances to a set of planes, as a symmetric 4x4 matrix
+// stored by its upper triangle
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    // The plane through `p` facing along the unit vector `n`
+    fn plane(n: &Vec3D, p: &Vec3D, weight: f64) -> Self {
+        let (a, b, c) = (n.x, n.y, n.z);
+        let d = -dot_product(n, p);
+        Self([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+        .scaled(weight)
+    }
+
+    fn scaled(self, s: f64) -> Self {
+        Self(self.0.map(|q| q * s))
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (q, o) in self.0.iter_mut().zip(other.0) {
+            *q += o;
+        }
+    }
+
+    fn error(&self, p: &Vec3D) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        q[0] * x * x
+            + q[4] * y * y
+            + q[7] * z * z
+            + 2.0 * (q[1] * x * y + q[2] * x * z + q[5] * y * z)
+            + 2.0 * (q[3] * x + q[6] * y + q[8] * z)
+            + q[9]
+    }
+}
+
+// The attributes of a triangle corner. Corners at the same position with
+// different attributes, such as either side of a UV seam, get separate wedges
+#[derive(Clone, Copy)]
+struct Wedge {
+    position: usize,
+    t: Vec2D,
+    n: Vec3D,
+    color: [f64; 3],
+}
+
+// Moving position `from` onto position `to`, which removes the triangles
+// between them. Stale entries are recognised by the positions' versions
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost.total_cmp(&other.cost) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed, so the heap gives the cheapest collapse first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+struct Decimator {
+    positions: Vec<Vec3D>,
+    wedges: Vec<Wedge>,
+    tris: Vec<[usize; 3]>,
+    alive: Vec<bool>,
+    // Unit normal of each triangle in the original mesh
+    facing: Vec<Vec3D>,
+    // Triangles around each position, including some that have been removed
+    around: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Decimator {
+    fn new(mesh: &Mesh, keep_normals: bool) -> Self {
+        // Adding 0.0 turns -0.0 into 0.0, so they weld together
+        let bits = |v: &Vec3D| [v.x, v.y, v.z].map(|c| (c + 0.0).to_bits());
+        let mut positions = vec![];
+        let mut position_ids = HashMap::new();
+        let mut wedges = vec![];
+        let mut wedge_ids = HashMap::new();
+        let mut tris = vec![];
+
+        for tri in &mesh.tris {
+            let corners = [0, 1, 2].map(|k| {
+                let p = tri.p[k];
+                let position = *position_ids.entry(bits(&p)).or_insert_with(|| {
+                    positions.push(p);
+                    positions.len() - 1
+                });
+                let n = if keep_normals {
+                    bits(&tri.n[k])
+                } else {
+                    [0; 3]
+                };
+                let key = (
+                    position,
+                    [tri.t[k].u, tri.t[k].v].map(f64::to_bits),
+                    n,
+                    tri.color[k].map(f64::to_bits),
+                );
+                *wedge_ids.entry(key).or_insert_with(|| {
+                    wedges.push(Wedge {
+                        position,
+                        t: Vec2D::new(tri.t[k].u, tri.t[k].v),
+                        n: tri.n[k],
+                        color: tri.color[k],
+                    });
+                    wedges.len() - 1
+                })
+            });
+            // Triangles with two corners in the same place have no area and
+            // would only get in the way
+            let [a, b, c] = corners.map(|w| wedges[w].position);
+            if a != b && b != c && c != a {
+                tris.push(corners);
+            }
+        }
+
+        let mut around = vec![vec![]; positions.len()];
+        for (i, tri) in tris.iter().enumerate() {
+            for &w in tri {
+                around[wedges[w].position].push(i);
+            }
+        }
+
+        let mut decimator = Self {
+            quadrics: vec![Quadric::default(); positions.len()],
+            versions: vec![0; positions.len()],
+            alive: vec![true; tris.len()],
+            facing: vec![],
+            positions,
+            wedges,
+            tris,
+            around,
+            heap: BinaryHeap::new(),
+        };
+        decimator.facing = (0..decimator.tris.len())
+            .map(|t| {
+                let [a, b, c] = decimator.corners(t).map(|i| decimator.positions[i]);
+                let n = cross_product(&(&b - &a), &(&c - &a));
+                if length(&n) > 0.0 {
+                    n.normalise()
+                } else {
+                    n
+                }
+            })
+            .collect();
+        decimator.add_quadrics();
+
+        for p in 0..decimator.positions.len() {
+            for n in decimator.neighbours(p) {
+                if n > p {
+                    decimator.push(p, n);
+                    decimator.push(n, p);
+                }
+            }
+        }
+        decimator
+    }
+
+    fn corners(&self, tri: usize) -> [usize; 3] {
+        self.tris[tri].map(|w| self.wedges[w].position)
+    }
+
+    // Each position starts with the planes of the triangles around it,
+    // weighted by area. Edges on a border or seam, or shared by more than two
+    // triangles, also get planes standing up along them, which keeps the edge
+    // in place
+    fn add_quadrics(&mut self) {
+        let mut edges: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (i, tri) in self.tris.iter().enumerate() {
+            let [a, b, c] = tri.map(|w| self.positions[self.wedges[w].position]);
+            let n = cross_product(&(&b - &a), &(&c - &a));
+            let area = length(&n) / 2.0;
+            if area > 0.0 {
+                let plane = Quadric::plane(&n.normalise(), &a, area);
+                for &w in tri {
+                    self.quadrics[self.wedges[w].position].add(&plane);
+                }
+            }
+            for k in 0..3 {
+                let (p, q) = (
+                    self.wedges[tri[k]].position,
+                    self.wedges[tri[(k + 1) % 3]].position,
+                );
+                edges.entry((p.min(q), p.max(q))).or_default().push(i);
+            }
+        }
+
+        for ((p, q), tris) in edges {
+            let wedge_at = |tri: usize, position: usize| {
+                self.tris[tri]
+                    .into_iter()
+                    .find(|&w| self.wedges[w].position == position)
+            };
+            let seam = tris.len() != 2
+                || wedge_at(tris[0], p) != wedge_at(tris[1], p)
+                || wedge_at(tris[0], q) != wedge_at(tris[1], q);
+            if !seam {
+                continue;
+            }
+
+            let edge = &self.positions[q] - &self.positions[p];
+            for tri in tris {
+                let [a, b, c] = self.corners(tri).map(|i| self.positions[i]);
+                let face = cross_product(&(&b - &a), &(&c - &a));
+                let n = cross_product(&edge, &face);
+                if length(&n) == 0.0 {
+                    continue;
+                }
+                let weight = SEAM_WEIGHT * dot_product(&edge, &edge);
+                let plane = Quadric::plane(&n.normalise(), &self.positions[p], weight);
+                self.quadrics[p].add(&plane);
+                self.quadrics[q].add(&plane);
+            }
+        }
+    }
+
+    fn neighbours(&self, p: usize) -> HashSet<usize> {
+        self.around[p]
+            .iter()
+            .filter(|&&t| self.alive[t])
+            .flat_map(|&t| self.corners(t))
+            .filter(|&n| n != p)
+            .collect()
+    }
+
+    fn push(&mut self, from: usize, to: usize) {
+        let mut q = self.quadrics[from];
+        q.add(&self.quadrics[to]);
+        self.heap.push(Collapse {
+            cost: q.error(&self.positions[to]),
+            from,
+            to,
+            versions: (self.versions[from], self.versions[to]),
+        });
+    }
+
+    // Which wedge each wedge at `from` becomes, if the collapse keeps the mesh
+    // in one piece, facing the same way, and with its seams where they were.
+    // Every wedge at `from` must share a triangle with the edge, so a corner on
+    // a seam can only slide along that seam
+    fn plan(&self, from: usize, to: usize) -> Option<HashMap<usize, usize>> {
+        let tris = self.around[from]
+            .iter()
+            .copied()
+            .filter(|&t| self.alive[t])
+            .collect::<Vec<_>>();
+        let (shared, moved): (Vec<usize>, Vec<usize>) = tris
+            .into_iter()
+            .partition(|&t| self.corners(t).contains(&to));
+        if shared.is_empty() {
+            return None;
+        }
+
+        let mut map = HashMap::new();
+        for &t in &shared {
+            let wedge = |position: usize| {
+                self.tris[t]
+                    .into_iter()
+                    .find(|&w| self.wedges[w].position == position)
+                    .unwrap()
+            };
+            if *map.entry(wedge(from)).or_insert(wedge(to)) != wedge(to) {
+                return None;
+            }
+        }
+
+        // Only the corners opposite the edge may be next to both ends, or the
+        // surface would be pinched together
+        let common = self
+            .neighbours(from)
+            .intersection(&self.neighbours(to))
+            .count();
+        if common != shared.len() {
+            return None;
+        }
+
+        let target = self.positions[to];
+        for t in moved {
+            let k = self.corners(t).iter().position(|&p| p == from).unwrap();
+            if !map.contains_key(&self.tris[t][k]) {
+                return None;
+            }
+            let mut after = self.corners(t).map(|i| self.positions[i]);
+            after[k] = target;
+            let [a, b, c] = after;
+            let normal = cross_product(&(&b - &a), &(&c - &a));
+            if length(&normal) == 0.0 {
+                return None;
+            }
+            // Triangles that had no area to start with only need to keep some
+            let original = &self.facing[t];
+            if length(original) > 0.0 && dot_product(&normal.normalise(), original) <= MIN_FACING {
+                return None;
+            }
+        }
+        Some(map)
+    }
+
+    fn collapse(&mut self, from: usize, to: usize, map: &HashMap<usize, usize>) -> usize {
+        let mut removed = 0;
+        for t in std::mem::take(&mut self.around[from]) {
+            if !self.alive[t] {
+                continue;
+            }
+            if self.corners(t).contains(&to) {
+                self.alive[t] = false;
+                removed += 1;
+            } else {
+                for w in &mut self.tris[t] {
+                    if let Some(&new) = map.get(w) {
+                        *w = new;
+                    }
+                }
+                self.around[to].push(t);
+            }
+        }
+        let alive = &self.alive;
+        self.around[to].retain(|&t| alive[t]);
+
+        let q = self.quadrics[from];
+        self.quadrics[to].add(&q);
+        self.versions[from] += 1;
+        self.versions[to] += 1;
+
+        for n in self.neighbours(to) {
+            self.push(n, to);
+            self.push(to, n);
+        }
+        removed
+    }
+
+    fn run(&mut self, target: usize) {
+        let mut count = self.tris.len();
+        while count > target {
+            let Some(c) = self.heap.pop() else {
+                break;
+            };
+            if c.versions != (self.versions[c.from], self.versions[c.to]) {
+                continue;
+            }
+            if let Some(map) = self.plan(c.from, c.to) {
+                count -= self.collapse(c.from, c.to, &map);
+            }
+        }
+    }
+}
+
+// Reduce a mesh towards `target` triangles by repeatedly merging the two ends
+// of whichever edge changes the shape least, measured with quadric error
+// metrics. Vertices stay where they were in the original, so their texture
+// coordinates and colours still fit. Texture and colour seams, hard edges and
+// open borders are kept, which can stop the mesh getting as small as asked.
+// Flat shaded meshes are given new face normals
+pub fn decimate(mesh: &Mesh, target: usize) -> Mesh {
+    let flat = mesh.tris.iter().all(|tri| {
+        let n = tri.normal();
+        tri.n.iter().all(|c| length(&(c - &n)) < 1e-9)
+    });
+    let mut decimator = Decimator::new(mesh, !flat);
+    decimator.run(target);
+
+    let mut out = Mesh::new(vec![]);
+    out.material = mesh.material;
+    for (i, tri) in decimator.tris.iter().enumerate() {
+        if !decimator.alive[i] {
+            continue;
+        }
+        let [a, b, c] = tri.map(|w| decimator.wedges[w]);
+        let p = |w: Wedge| decimator.positions[w.position];
+        let mut tri = Triangle::new_uv(p(a), p(b), p(c), a.t, b.t, c.t);
+        if !flat {
+            tri.n = [a.n, b.n, c.n];
+        }
+        tri.color = [a.color, b.color, c.color];
+        out.tris.push(tri);
+    }
+    out
+}
+
+// A mesh used from `distance` away and further, until the next level's
+pub struct LodLevel {
+    pub mesh: Mesh,
+    pub distance: f64,
+}
+
+// Copies of a mesh with fewer and fewer triangles, for drawing it with less
+// detail the further away it is
+pub struct LodMesh {
+    pub levels: Vec<LodLevel>,
+}
+
+impl LodMesh {
+    // Level 0 is `mesh` itself. Each level after it has `ratio` times the
+    // triangles of the one before and starts at twice its distance, the first
+    // at `distance`. Fewer than `count` levels are made if the mesh can't be
+    // reduced any further
+    pub fn new(mesh: Mesh, count: usize, ratio: f64, distance: f64) -> Self {
+        let mut levels = vec![LodLevel {
+            mesh,
+            distance: 0.0,
+        }];
+        for i in 1..count {
+            let previous = &levels[i - 1].mesh;
+            let target = (previous.tris.len() as f64 * ratio) as usize;
+            let mesh = decimate(previous, target);
+            if mesh.tris.len() >= previous.tris.len() {
+                break;
+            }
+            levels.push(LodLevel {
+                mesh,
+                distance: distance * 2f64.powi(i as i32 - 1),
+            });
+        }
+        Self { levels }
+    }
+
+    pub fn level_for_distance(&self, distance: f64) -> usize {
+        self.levels
+            .iter()
+            .rposition(|level| distance >= level.distance)
+            .unwrap_or(0)
+    }
+}
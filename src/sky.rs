@@ -0,0 +1,148 @@
+use image::{DynamicImage, GenericImageView, ImageError, ImageReader};
+
+use crate::{
+    mat4x4::{inverse, multiply_vector, Mat4x4},
+    vec3d::Vec3D,
+};
+
+// Six images surrounding the scene, in the order +x, -x, +y, -y, +z, -z
+pub struct Cubemap {
+    pub faces: [DynamicImage; 6],
+}
+
+impl Cubemap {
+    pub fn from_files(paths: [&str; 6]) -> Result<Self, ImageError> {
+        let load =
+            |path: &str| -> Result<DynamicImage, ImageError> { ImageReader::open(path)?.decode() };
+        Ok(Self {
+            faces: [
+                load(paths[0])?,
+                load(paths[1])?,
+                load(paths[2])?,
+                load(paths[3])?,
+                load(paths[4])?,
+                load(paths[5])?,
+            ],
+        })
+    }
+
+    pub fn sample(&self, dir: &Vec3D) -> [u8; 4] {
+        let (ax, ay, az) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+
+        // Pick the face the direction points at most, and where on that face
+        // it lands, with u and v running from -1.0 to 1.0
+        let (face, u, v) = if ax >= ay && ax >= az {
+            if dir.x > 0.0 {
+                (0, -dir.z / ax, -dir.y / ax)
+            } else {
+                (1, dir.z / ax, -dir.y / ax)
+            }
+        } else if ay >= az {
+            if dir.y > 0.0 {
+                (2, dir.x / ay, dir.z / ay)
+            } else {
+                (3, dir.x / ay, -dir.z / ay)
+            }
+        } else if dir.z > 0.0 {
+            (4, dir.x / az, -dir.y / az)
+        } else {
+            (5, -dir.x / az, -dir.y / az)
+        };
+
+        let tex = &self.faces[face];
+        let x = (((u + 1.0) * 0.5 * tex.width() as f64) as u32).min(tex.width() - 1);
+        let y = (((v + 1.0) * 0.5 * tex.height() as f64) as u32).min(tex.height() - 1);
+        tex.get_pixel(x, y).0
+    }
+}
+
+// What is drawn behind the scene
+pub enum Sky {
+    Color([u8; 4]),
+    // Blends from the horizon up to the zenith above, and drops to the ground
+    // colour below the horizon
+    Gradient {
+        zenith: [u8; 4],
+        horizon: [u8; 4],
+        ground: [u8; 4],
+    },
+    Cubemap(Box<Cubemap>),
+}
+
+impl Sky {
+    // Colour of the sky seen along a world space direction
+    pub fn color(&self, dir: &Vec3D) -> [u8; 4] {
+        match self {
+            Sky::Color(c) => *c,
+            Sky::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => {
+                let l = (dir.x * dir.x + dir.y * dir.y + dir.z * dir.z).sqrt();
+                let up = dir.y / l;
+                if up >= 0.0 {
+                    blend(horizon, zenith, up.sqrt())
+                } else {
+                    blend(horizon, ground, (-up * 4.0).min(1.0))
+                }
+            }
+            Sky::Cubemap(cubemap) => cubemap.sample(dir),
+        }
+    }
+
+    // Fill the whole frame with the sky, replacing clearing the screen
+    pub fn draw(&self, frame: &mut [u8], canvas_width: i32, mat_view_proj: &Mat4x4) {
+        if let Sky::Color(c) = self {
+            for pixel in frame.chunks_exact_mut(4) {
+                pixel.copy_from_slice(c);
+            }
+            return;
+        }
+
+        let rays = ScreenRays::new(
+            canvas_width,
+            frame.len() as i32 / 4 / canvas_width,
+            mat_view_proj,
+        );
+        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+            let x = i as i32 % canvas_width;
+            let y = i as i32 / canvas_width;
+            pixel.copy_from_slice(&self.color(&rays.direction(x, y)));
+        }
+    }
+}
+
+// World space view directions through pixels, undoing the viewer's mapping of
+// normalised device coordinates to the screen
+pub struct ScreenRays {
+    inv_view_proj: Mat4x4,
+    width: f64,
+    height: f64,
+}
+
+impl ScreenRays {
+    pub fn new(canvas_width: i32, canvas_height: i32, mat_view_proj: &Mat4x4) -> Self {
+        Self {
+            inv_view_proj: inverse(mat_view_proj).unwrap_or_default(),
+            width: canvas_width as f64,
+            height: canvas_height as f64,
+        }
+    }
+
+    pub fn direction(&self, x: i32, y: i32) -> Vec3D {
+        let ndc_x = 1.0 - 2.0 * (x as f64 + 0.5) / self.width;
+        let ndc_y = 1.0 - 2.0 * (y as f64 + 0.5) / self.height;
+        let unproject = |z: f64| {
+            let p = multiply_vector(&self.inv_view_proj, &Vec3D::new(ndc_x, ndc_y, z));
+            &p / p.w
+        };
+        &unproject(1.0) - &unproject(0.0)
+    }
+}
+
+// Linear blend from `a` at 0.0 to `b` at 1.0
+pub fn blend(a: &[u8; 4], b: &[u8; 4], t: f64) -> [u8; 4] {
+    let channel = |i: usize| (a[i] as f64 + (b[i] as f64 - a[i] as f64) * t) as u8;
+    [channel(0), channel(1), channel(2), channel(3)]
+}
@@ -0,0 +1,218 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+};
+
+use crate::{
+    mesh::{LoadError, Mesh},
+    triangle::Triangle,
+    vec3d::Vec3D,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StlFormat {
+    Ascii,
+    Binary,
+}
+
+// Load an ASCII or binary STL file. The facet normals in the file are used for
+// the triangles' normals, unless they are missing (all zero), in which case the
+// normal comes from the winding. Positions are kept as they are in the file
+pub fn load_stl(filename: &str) -> Result<Mesh, LoadError> {
+    let bytes = fs::read(filename)?;
+    let tris = if is_binary(&bytes) {
+        parse_binary(&bytes)
+    } else {
+        parse_ascii(&bytes)?
+    };
+
+    let mut mesh = Mesh::new(vec![]);
+    mesh.tris = tris;
+    Ok(mesh)
+}
+
+pub fn save_stl(mesh: &Mesh, filename: &str, format: StlFormat) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(filename)?);
+    match format {
+        StlFormat::Ascii => {
+            writeln!(out, "solid mesh")?;
+            for tri in &mesh.tris {
+                let n = tri.normal();
+                writeln!(out, "  facet normal {} {} {}", n.x, n.y, n.z)?;
+                writeln!(out, "    outer loop")?;
+                for p in &tri.p {
+                    writeln!(out, "      vertex {} {} {}", p.x, p.y, p.z)?;
+                }
+                writeln!(out, "    endloop")?;
+                writeln!(out, "  endfacet")?;
+            }
+            writeln!(out, "endsolid mesh")?;
+        }
+        StlFormat::Binary => {
+            let mut header = [0u8; 80];
+            let name = b"binary STL";
+            header[..name.len()].copy_from_slice(name);
+            out.write_all(&header)?;
+            out.write_all(&(mesh.tris.len() as u32).to_le_bytes())?;
+            for tri in &mesh.tris {
+                let n = tri.normal();
+                for v in [&n, &tri.p[0], &tri.p[1], &tri.p[2]] {
+                    for c in [v.x, v.y, v.z] {
+                        out.write_all(&(c as f32).to_le_bytes())?;
+                    }
+                }
+                // Attribute byte count, unused
+                out.write_all(&0u16.to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+// Binary files can also start with "solid", so they are recognised by their
+// size matching the triangle count in the header instead. Some exporters add
+// bytes after the last facet, so a longer file is binary too unless it looks
+// like text, which never has the zero bytes binary facets are full of
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < 84 {
+        return false;
+    }
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    let size = 84 + count * 50;
+    let ascii = bytes.starts_with(b"solid") && !bytes.contains(&0);
+    bytes.len() == size || (bytes.len() > size && !ascii)
+}
+
+fn parse_binary(bytes: &[u8]) -> Vec<Triangle> {
+    bytes[84..]
+        .chunks_exact(50)
+        .map(|facet| {
+            let float = |i: usize| {
+                let b = &facet[i * 4..i * 4 + 4];
+                f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+            };
+            let vec = |i: usize| Vec3D::new(float(i * 3), float(i * 3 + 1), float(i * 3 + 2));
+            make_triangle(vec(0), [vec(1), vec(2), vec(3)])
+        })
+        .collect()
+}
+
+fn parse_ascii(bytes: &[u8]) -> Result<Vec<Triangle>, LoadError> {
+    let text = std::str::from_utf8(bytes)
+        .map_err(|_| LoadError::Format("not a binary STL or valid ASCII STL".to_string()))?;
+
+    let mut tris = vec![];
+    let mut normal = Vec3D::empty();
+    let mut verts = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: &str| LoadError::Parse {
+            line: i + 1,
+            message: message.to_string(),
+        };
+        let mut words = line.split_ascii_whitespace();
+        match words.next() {
+            Some("facet") => {
+                if words.next() != Some("normal") {
+                    return Err(error("expected facet normal"));
+                }
+                normal = parse_vec(words).ok_or_else(|| error("bad facet normal"))?;
+                verts.clear();
+            }
+            Some("vertex") => {
+                verts.push(parse_vec(words).ok_or_else(|| error("bad vertex"))?);
+            }
+            Some("endfacet") => {
+                let [a, b, c] = verts[..] else {
+                    return Err(error("facet doesn't have 3 vertices"));
+                };
+                tris.push(make_triangle(normal, [a, b, c]));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(tris)
+}
+
+fn parse_vec<'a>(mut nums: impl Iterator<Item = &'a str>) -> Option<Vec3D> {
+    let mut num = || nums.next()?.parse::<f64>().ok();
+    Some(Vec3D::new(num()?, num()?, num()?))
+}
+
+fn make_triangle(normal: Vec3D, p: [Vec3D; 3]) -> Triangle {
+    let mut tri = Triangle::new(p[0], p[1], p[2]);
+    if normal.x != 0.0 || normal.y != 0.0 || normal.z != 0.0 {
+        let n = normal.normalise();
+        tri.n = [n, n, n];
+    }
+    tri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3d::length;
+
+    // A closed tetrahedron with its faces wound outwards
+    fn tetrahedron() -> Mesh {
+        let p = [
+            Vec3D::new(0.0, 0.0, 0.0),
+            Vec3D::new(1.0, 0.0, 0.0),
+            Vec3D::new(0.0, 1.0, 0.0),
+            Vec3D::new(0.0, 0.0, 1.0),
+        ];
+        let mut mesh = Mesh::new(vec![]);
+        for [a, b, c] in [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]] {
+            mesh.tris
+                .push(make_triangle(Vec3D::empty(), [p[a], p[b], p[c]]));
+        }
+        mesh
+    }
+
+    fn round_trip(format: StlFormat) {
+        let mesh = tetrahedron();
+        let path = std::env::temp_dir().join(format!("engine_3d_round_trip_{:?}.stl", format));
+        let path = path.to_str().unwrap();
+        save_stl(&mesh, path, format).unwrap();
+        let loaded = load_stl(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.tris.len(), mesh.tris.len());
+        for (a, b) in mesh.tris.iter().zip(&loaded.tris) {
+            for i in 0..3 {
+                assert!(length(&(&a.p[i] - &b.p[i])) < 1e-6);
+                assert!(length(&(&a.normal() - &b.n[i])) < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn ascii_round_trip() {
+        round_trip(StlFormat::Ascii);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        round_trip(StlFormat::Binary);
+    }
+
+    #[test]
+    fn binary_detection() {
+        let mut bytes = vec![0; 84 + 50];
+        bytes[..5].copy_from_slice(b"solid");
+        bytes[80] = 1;
+        assert!(is_binary(&bytes));
+
+        // Padding after the last facet
+        bytes.extend_from_slice(&[0; 16]);
+        assert!(is_binary(&bytes));
+
+        // Too short for the facets the header promises
+        bytes.truncate(84 + 20);
+        assert!(!is_binary(&bytes));
+
+        let ascii = b"solid mesh\n  facet normal 0 0 1\n    outer loop\n      vertex 0 0 0\n      vertex 1 0 0\n      vertex 0 1 0\n    endloop\n  endfacet\nendsolid mesh\n";
+        assert!(!is_binary(ascii));
+    }
+}
@@ -0,0 +1,336 @@
+use image::{DynamicImage, ImageError};
+
+use crate::{
+    aabb::Aabb,
+    bvh::Bvh,
+    mesh::{LoadError, Mesh},
+    triangle::Triangle,
+    vec2d::Vec2D,
+    vec3d::Vec3D,
+};
+
+// Heights sampled on a square grid in the xz plane, stored row by row. Rows
+// run along +z and columns along +x, starting from `origin`
+#[derive(Clone, Debug)]
+pub struct Heightmap {
+    pub columns: usize,
+    pub rows: usize,
+    pub heights: Vec<f64>,
+    pub cell_size: f64,
+    pub origin: Vec3D,
+}
+
+impl Heightmap {
+    // One sample per pixel of a grayscale image, with black at height 0.0 and
+    // white at `height`. The grid is centered on the origin and the image's top
+    // row is at -z
+    pub fn from_image(image: &DynamicImage, cell_size: f64, height: f64) -> Self {
+        let gray = image.to_luma32f();
+        let (columns, rows) = (gray.width() as usize, gray.height() as usize);
+        Self {
+            columns,
+            rows,
+            heights: gray.pixels().map(|p| p.0[0] as f64 * height).collect(),
+            cell_size,
+            origin: Vec3D::new(
+                -((columns.max(1) - 1) as f64) * cell_size / 2.0,
+                0.0,
+                -((rows.max(1) - 1) as f64) * cell_size / 2.0,
+            ),
+        }
+    }
+
+    pub fn load(filename: &str, cell_size: f64, height: f64) -> Result<Self, LoadError> {
+        let image = image::open(filename).map_err(|e| match e {
+            ImageError::IoError(e) => LoadError::Io(e),
+            e => LoadError::Format(e.to_string()),
+        })?;
+        if image.width() < 2 || image.height() < 2 {
+            return Err(LoadError::Format(
+                "heightmap must be at least 2x2 pixels".to_string(),
+            ));
+        }
+        Ok(Self::from_image(&image, cell_size, height))
+    }
+
+    // Height of a sample, with positions past the edges clamped to them
+    pub fn height(&self, column: usize, row: usize) -> f64 {
+        let c = column.min(self.columns - 1);
+        let r = row.min(self.rows - 1);
+        self.heights[r * self.columns + c]
+    }
+
+    pub fn position(&self, column: usize, row: usize) -> Vec3D {
+        Vec3D::new(
+            self.origin.x + column as f64 * self.cell_size,
+            self.origin.y + self.height(column, row),
+            self.origin.z + row as f64 * self.cell_size,
+        )
+    }
+
+    // Normal from the slope between neighbouring samples, or towards one side
+    // at the edges
+    pub fn normal(&self, column: usize, row: usize) -> Vec3D {
+        let (c0, c1) = (column.saturating_sub(1), (column + 1).min(self.columns - 1));
+        let (r0, r1) = (row.saturating_sub(1), (row + 1).min(self.rows - 1));
+        let slope_x = (self.height(c1, row) - self.height(c0, row))
+            / ((c1 - c0).max(1) as f64 * self.cell_size);
+        let slope_z = (self.height(column, r1) - self.height(column, r0))
+            / ((r1 - r0).max(1) as f64 * self.cell_size);
+        Vec3D::new(-slope_x, 1.0, -slope_z).normalise()
+    }
+
+    // Height of the surface at a world x and z, on the same triangles as the
+    // full detail mesh, or None outside the grid
+    pub fn height_at(&self, x: f64, z: f64) -> Option<f64> {
+        if self.columns < 2 || self.rows < 2 {
+            return None;
+        }
+        let fx = (x - self.origin.x) / self.cell_size;
+        let fz = (z - self.origin.z) / self.cell_size;
+        if !(0.0..=(self.columns - 1) as f64).contains(&fx)
+            || !(0.0..=(self.rows - 1) as f64).contains(&fz)
+        {
+            return None;
+        }
+
+        let c = (fx as usize).min(self.columns - 2);
+        let r = (fz as usize).min(self.rows - 2);
+        let (u, v) = (fx - c as f64, fz - r as f64);
+        let ha = self.height(c, r);
+        let hb = self.height(c + 1, r);
+        let hc = self.height(c + 1, r + 1);
+        let hd = self.height(c, r + 1);
+
+        // Cells are split along the diagonal from a to c
+        let h = if u >= v {
+            ha + u * (hb - ha) + v * (hc - hb)
+        } else {
+            ha + v * (hd - ha) + u * (hc - hd)
+        };
+        Some(self.origin.y + h)
+    }
+}
+
+// A square block of the terrain, from sample `first` to sample `last`
+// (column, row) inclusive. `mesh` is built at `lod`, where each level skips
+// twice as many samples as the one before
+pub struct TerrainChunk {
+    pub first: (usize, usize),
+    pub last: (usize, usize),
+    pub bounds: Aabb,
+    pub lod: usize,
+    pub mesh: Mesh,
+    pub bvh: Bvh,
+    // Sample spacing along the top, bottom, left and right edges
+    edges: [usize; 4],
+}
+
+// Heightmap terrain split into chunks, each drawn with less detail the further
+// it is from the camera. Where neighbouring chunks differ in detail, the
+// finer chunk's edge follows the coarser one so no cracks open between them
+pub struct Terrain {
+    pub heightmap: Heightmap,
+    pub chunk_cells: usize,
+    pub levels: usize,
+    // Chunks closer than this use full detail, and each level after that is
+    // used up to twice the distance of the one before
+    pub lod_distance: f64,
+    pub chunks: Vec<TerrainChunk>,
+    chunk_columns: usize,
+    chunk_rows: usize,
+}
+
+impl Terrain {
+    // `chunk_cells` is rounded up to a power of two, and `levels` is limited to
+    // what a chunk of that size can skip down to
+    pub fn new(heightmap: Heightmap, chunk_cells: usize, levels: usize, lod_distance: f64) -> Self {
+        let chunk_cells = chunk_cells.max(1).next_power_of_two();
+        let levels = levels.clamp(1, chunk_cells.trailing_zeros() as usize + 1);
+        let count = |samples: usize| (samples.max(2) - 1).div_ceil(chunk_cells);
+        let (chunk_columns, chunk_rows) = (count(heightmap.columns), count(heightmap.rows));
+
+        let mut chunks = vec![];
+        for row in 0..chunk_rows {
+            for column in 0..chunk_columns {
+                let first = (column * chunk_cells, row * chunk_cells);
+                let last = (
+                    (first.0 + chunk_cells).min(heightmap.columns - 1),
+                    (first.1 + chunk_cells).min(heightmap.rows - 1),
+                );
+                let mut bounds = Aabb::empty();
+                for r in first.1..=last.1 {
+                    for c in first.0..=last.0 {
+                        bounds.expand(&heightmap.position(c, r));
+                    }
+                }
+                chunks.push(TerrainChunk {
+                    first,
+                    last,
+                    bounds,
+                    lod: 0,
+                    mesh: Mesh::new(vec![]),
+                    bvh: Bvh::new(&Mesh::new(vec![])),
+                    edges: [0; 4],
+                });
+            }
+        }
+
+        let mut terrain = Self {
+            heightmap,
+            chunk_cells,
+            levels,
+            lod_distance,
+            chunks,
+            chunk_columns,
+            chunk_rows,
+        };
+        for i in 0..terrain.chunks.len() {
+            terrain.build_chunk(i, 0, [1; 4]);
+        }
+        terrain
+    }
+
+    // Pick each chunk's level of detail for a camera at `camera`, in the same
+    // space as the heightmap, and rebuild the chunks whose detail or edges
+    // changed. Returns how many were rebuilt
+    pub fn update(&mut self, camera: &Vec3D) -> usize {
+        let lods = self
+            .chunks
+            .iter()
+            .map(|chunk| self.lod_for_distance(chunk.bounds.distance(camera)))
+            .collect::<Vec<_>>();
+
+        let mut rebuilt = 0;
+        for i in 0..self.chunks.len() {
+            let (column, row) = (i % self.chunk_columns, i / self.chunk_columns);
+            let lod = lods[i];
+            let neighbours = [
+                (row > 0).then(|| i - self.chunk_columns),
+                (row + 1 < self.chunk_rows).then(|| i + self.chunk_columns),
+                (column > 0).then(|| i - 1),
+                (column + 1 < self.chunk_columns).then(|| i + 1),
+            ];
+            let edges = neighbours.map(|n| 1 << n.map_or(lod, |n| lod.max(lods[n])));
+
+            if lod != self.chunks[i].lod || edges != self.chunks[i].edges {
+                self.build_chunk(i, lod, edges);
+                rebuilt += 1;
+            }
+        }
+        rebuilt
+    }
+
+    pub fn lod_for_distance(&self, distance: f64) -> usize {
+        if distance < self.lod_distance || self.lod_distance <= 0.0 {
+            return 0;
+        }
+        let lod = (distance / self.lod_distance).log2().floor() as usize + 1;
+        lod.min(self.levels - 1)
+    }
+
+    pub fn height_at(&self, x: f64, z: f64) -> Option<f64> {
+        self.heightmap.height_at(x, z)
+    }
+
+    // Every chunk's triangles together, as drawn at their current detail
+    pub fn mesh(&self) -> Mesh {
+        let mut mesh = Mesh::new(vec![]);
+        mesh.tris = self
+            .chunks
+            .iter()
+            .flat_map(|chunk| chunk.mesh.tris.iter().copied())
+            .collect();
+        mesh
+    }
+
+    // BVH over the triangles of `mesh()`, joined from the chunks' own BVHs
+    pub fn bvh(&self) -> Bvh {
+        let mut first = 0;
+        let parts = self
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let part = (&chunk.bvh, first);
+                first += chunk.mesh.tris.len();
+                part
+            })
+            .collect::<Vec<_>>();
+        Bvh::join(&parts)
+    }
+
+    // Build a chunk's grid with samples `1 << lod` apart. Samples on an edge
+    // that lie between the samples of a coarser neighbour are moved onto the
+    // one before them, which folds away the triangles beside them and leaves
+    // the edge made of the same segments as the neighbour's
+    fn build_chunk(&mut self, index: usize, lod: usize, edges: [usize; 4]) {
+        let map = &self.heightmap;
+        let chunk = &self.chunks[index];
+        let ((c0, r0), (c1, r1)) = (chunk.first, chunk.last);
+        let step = 1 << lod;
+
+        let samples = |start: usize, end: usize| {
+            let mut s = (start..end).step_by(step).collect::<Vec<_>>();
+            s.push(end);
+            s
+        };
+        let (columns, rows) = (samples(c0, c1), samples(r0, r1));
+
+        // Chunks start on multiples of every step, so the coarser samples are
+        // the multiples of its step, and the end of the map
+        let snap = |v: usize, spacing: usize, end: usize| {
+            if v.is_multiple_of(spacing) || v == end {
+                v
+            } else {
+                v / spacing * spacing
+            }
+        };
+        let sample = |c: usize, r: usize| {
+            if r == r0 {
+                (snap(c, edges[0], c1), r)
+            } else if r == r1 {
+                (snap(c, edges[1], c1), r)
+            } else if c == c0 {
+                (c, snap(r, edges[2], r1))
+            } else if c == c1 {
+                (c, snap(r, edges[3], r1))
+            } else {
+                (c, r)
+            }
+        };
+
+        let uv = |(c, r): (usize, usize)| {
+            Vec2D::new(
+                c as f64 / (map.columns - 1) as f64,
+                r as f64 / (map.rows - 1) as f64,
+            )
+        };
+        let mut tris = vec![];
+        for rs in rows.windows(2) {
+            for cs in columns.windows(2) {
+                let a = sample(cs[0], rs[0]);
+                let b = sample(cs[1], rs[0]);
+                let c = sample(cs[1], rs[1]);
+                let d = sample(cs[0], rs[1]);
+                // Wound so the face normal points up
+                for corners in [[a, c, b], [a, d, c]] {
+                    let [p, q, s] = corners;
+                    if p == q || q == s || s == p {
+                        continue;
+                    }
+                    let [pa, pb, pc] = corners.map(|(c, r)| map.position(c, r));
+                    let [ta, tb, tc] = corners.map(uv);
+                    let mut tri = Triangle::new_uv(pa, pb, pc, ta, tb, tc);
+                    tri.n = corners.map(|(c, r)| map.normal(c, r));
+                    tris.push(tri);
+                }
+            }
+        }
+
+        let chunk = &mut self.chunks[index];
+        chunk.mesh.tris = tris;
+        chunk.bvh = Bvh::new(&chunk.mesh);
+        chunk.lod = lod;
+        chunk.edges = edges;
+    }
+}
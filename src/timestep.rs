@@ -0,0 +1,112 @@
+use std::time::{Duration, Instant};
+
+// Runs simulation updates at a fixed rate no matter how often frames are drawn.
+// Frame times are banked and spent in whole steps; whatever is left over says
+// how far to blend between the last two simulated states when drawing
+pub struct FixedTimestep {
+    pub step: Duration,
+    // Longest frame time that is simulated, so a stall (like dragging the
+    // window) doesn't turn into a long burst of catch-up steps
+    pub max_frame_time: Duration,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    pub fn new(steps_per_second: f64) -> Self {
+        Self {
+            step: Duration::from_secs_f64(1.0 / steps_per_second),
+            max_frame_time: Duration::from_millis(250),
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    // Bank a frame's time, returning how many steps should be run for it
+    pub fn advance(&mut self, frame_time: Duration) -> u32 {
+        self.accumulator += frame_time.min(self.max_frame_time);
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        steps
+    }
+
+    // How far from the previous step to the latest one the frame being drawn
+    // is, from 0.0 to 1.0
+    pub fn alpha(&self) -> f64 {
+        self.accumulator.as_secs_f64() / self.step.as_secs_f64()
+    }
+}
+
+// Spaces frames out so no more than `max_fps` are drawn each second
+pub struct FrameLimiter {
+    pub max_fps: Option<f64>,
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(max_fps: Option<f64>) -> Self {
+        Self {
+            max_fps,
+            next_frame: Instant::now(),
+        }
+    }
+
+    // When the next frame is due, or None if it should be drawn now
+    pub fn wait_until(&self, now: Instant) -> Option<Instant> {
+        self.max_fps?;
+        (now < self.next_frame).then_some(self.next_frame)
+    }
+
+    // Schedule the next frame. Frames are spaced from when the previous one
+    // was due rather than when it started, so small delays don't lower the
+    // frame rate, unless drawing has fallen a whole frame behind
+    pub fn frame_started(&mut self, now: Instant) {
+        let Some(max_fps) = self.max_fps else {
+            return;
+        };
+        let period = Duration::from_secs_f64(1.0 / max_fps);
+        self.next_frame += period;
+        if self.next_frame < now {
+            self.next_frame = now + period;
+        }
+    }
+}
+
+// Frames per second averaged over about half a second, so the number shown is
+// steady enough to read
+pub struct FpsCounter {
+    fps: f64,
+    frames: u32,
+    elapsed: Duration,
+}
+
+impl FpsCounter {
+    pub fn new() -> Self {
+        Self {
+            fps: 0.0,
+            frames: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    pub fn tick(&mut self, frame_time: Duration) {
+        self.frames += 1;
+        self.elapsed += frame_time;
+        if self.elapsed >= Duration::from_millis(500) {
+            self.fps = self.frames as f64 / self.elapsed.as_secs_f64();
+            self.frames = 0;
+            self.elapsed = Duration::ZERO;
+        }
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+}
+
+impl Default for FpsCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
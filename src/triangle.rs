@@ -1,36 +1,117 @@
-use crate::vec2d::Vec2D;
-
-use super::vec3d::Vec3D;
+use crate::{
+    material::CullMode,
+    vec2d::Vec2D,
+    vec3d::{cross_product, Vec3D},
+    vertex::Vertex,
+};
 
+// Besides positions and texture coordinates, triangles carry per-vertex normals
+// (the face normal unless a loader provides smooth ones) and the other vertex
+// attributes the rendering pipeline fills in on the way to the rasterizer.
+// `cull` comes from the triangle's material, so meshes merged from several
+// materials keep each one's culling
 #[derive(Clone, Copy)]
 pub struct Triangle {
     pub p: [Vec3D; 3],
     pub t: [Vec2D; 3],
+    pub n: [Vec3D; 3],
+    pub world: [Vec3D; 3],
+    pub diffuse: [[f64; 3]; 3],
+    pub specular: [[f64; 3]; 3],
+    pub color: [[f64; 3]; 3],
     pub col: [u8; 4],
+    pub cull: CullMode,
 }
 
 impl Triangle {
     pub fn new(v1: Vec3D, v2: Vec3D, v3: Vec3D) -> Self {
-        Self {
-            p: [v1, v2, v3],
-            t: [Vec2D::empty(), Vec2D::empty(), Vec2D::empty()],
-            col: [0xff, 0xff, 0xff, 0xff],
-        }
+        Self::new_uv(v1, v2, v3, Vec2D::empty(), Vec2D::empty(), Vec2D::empty())
     }
 
     pub fn new_uv(v1: Vec3D, v2: Vec3D, v3: Vec3D, uv1: Vec2D, uv2: Vec2D, uv3: Vec2D) -> Self {
-        Self {
-            p: [v1, v2, v3],
-            t: [uv1, uv2, uv3],
-            col: [0xff, 0xff, 0xff, 0xff],
-        }
+        let mut tri = Self::empty();
+        tri.p = [v1, v2, v3];
+        tri.t = [uv1, uv2, uv3];
+        let n = tri.normal();
+        tri.n = [n, n, n];
+        tri
     }
 
     pub fn empty() -> Self {
         Self {
             p: [Vec3D::empty(), Vec3D::empty(), Vec3D::empty()],
             t: [Vec2D::empty(), Vec2D::empty(), Vec2D::empty()],
+            n: [Vec3D::empty(), Vec3D::empty(), Vec3D::empty()],
+            world: [Vec3D::empty(), Vec3D::empty(), Vec3D::empty()],
+            diffuse: [[1.0; 3]; 3],
+            specular: [[0.0; 3]; 3],
+            color: [[1.0; 3]; 3],
             col: [0xff, 0xff, 0xff, 0xff],
+            cull: CullMode::Back,
+        }
+    }
+
+    // Unit normal of the face, from the winding of its vertices. Degenerate
+    // triangles give a zero vector
+    pub fn normal(&self) -> Vec3D {
+        let line1 = &self.p[1] - &self.p[0];
+        let line2 = &self.p[2] - &self.p[0];
+        let n = cross_product(&line1, &line2);
+        if n.x == 0.0 && n.y == 0.0 && n.z == 0.0 {
+            return n;
+        }
+        n.normalise()
+    }
+
+    pub fn vertex(&self, i: usize) -> Vertex {
+        Vertex {
+            p: self.p[i],
+            t: self.t[i],
+            n: self.n[i],
+            world: self.world[i],
+            diffuse: self.diffuse[i],
+            specular: self.specular[i],
+            color: self.color[i],
+        }
+    }
+
+    pub fn vertices(&self) -> [Vertex; 3] {
+        [self.vertex(0), self.vertex(1), self.vertex(2)]
+    }
+
+    // Reverse the winding by swapping the last two corners, with all their
+    // attributes. The normals are left pointing the way they did
+    pub fn flip(&mut self) {
+        let [a, b, c] = self.vertices();
+        *self = Triangle::from_vertices(self, [a, c, b]);
+    }
+
+    // Build a triangle from three vertices, copying per-triangle data such as
+    // the colour from `like`
+    pub fn from_vertices(like: &Triangle, v: [Vertex; 3]) -> Self {
+        let mut tri = *like;
+        for (i, vertex) in v.iter().enumerate() {
+            tri.p[i] = vertex.p;
+            tri.t[i] = vertex.t;
+            tri.n[i] = vertex.n;
+            tri.world[i] = vertex.world;
+            tri.diffuse[i] = vertex.diffuse;
+            tri.specular[i] = vertex.specular;
+            tri.color[i] = vertex.color;
+        }
+        tri
+    }
+
+    // Split a convex polygon, such as the output of clipping, into a fan of
+    // triangles around its first vertex. The winding of the polygon is kept
+    pub fn fan(like: &Triangle, polygon: &[Vertex]) -> Vec<Triangle> {
+        let mut tris = vec![];
+        for i in 1..polygon.len().saturating_sub(1) {
+            tris.push(Triangle::from_vertices(
+                like,
+                [polygon[0], polygon[i], polygon[i + 1]],
+            ));
         }
+        tris
     }
 }
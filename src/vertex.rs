@@ -0,0 +1,116 @@
+use crate::{vec2d::Vec2D, vec3d::Vec3D};
+
+// Anything that can be linearly interpolated between two values. Clipping is
+// written in terms of this, so any per-vertex attribute implementing it is
+// carried through to the new vertices clipping creates
+pub trait Interpolate {
+    fn lerp(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl<const N: usize> Interpolate for [f64; N] {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        let mut out = *self;
+        for (o, b) in out.iter_mut().zip(other) {
+            *o = o.lerp(b, t);
+        }
+        out
+    }
+}
+
+// All four components are interpolated, so this works for homogeneous clip
+// space positions too
+impl Interpolate for Vec3D {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        Vec3D {
+            x: self.x.lerp(&other.x, t),
+            y: self.y.lerp(&other.y, t),
+            z: self.z.lerp(&other.z, t),
+            w: self.w.lerp(&other.w, t),
+        }
+    }
+}
+
+impl Interpolate for Vec2D {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        Vec2D {
+            u: self.u.lerp(&other.u, t),
+            v: self.v.lerp(&other.v, t),
+            w: self.w.lerp(&other.w, t),
+        }
+    }
+}
+
+// Every attribute of one corner of a triangle. New per-vertex attributes go
+// here and in `Triangle`, with matching lines in `lerp` and the perspective
+// functions. `n` is the surface normal and `world` the world space position,
+// used for per-pixel lighting, while `diffuse` and `specular` hold light
+// already evaluated at the vertex. `color` is the model's own vertex colour,
+// white for models without one
+#[derive(Clone, Copy, Debug)]
+pub struct Vertex {
+    pub p: Vec3D,
+    pub t: Vec2D,
+    pub n: Vec3D,
+    pub world: Vec3D,
+    pub diffuse: [f64; 3],
+    pub specular: [f64; 3],
+    pub color: [f64; 3],
+}
+
+impl Vertex {
+    // Divide a clip space vertex by w. Attributes are divided too, which makes
+    // them linear in screen space so the rasterizer can interpolate them
+    // directly, and `t.w` keeps 1 / w so `perspective_correct` can undo it
+    pub fn perspective_divide(&self) -> Vertex {
+        let inv_w = 1.0 / self.p.w;
+        let mut v = self.scaled(inv_w);
+        v.p = Vec3D::new(self.p.x * inv_w, self.p.y * inv_w, self.p.z * inv_w);
+        v.t.w = inv_w;
+        v
+    }
+
+    // Recover the attributes of a vertex interpolated in screen space
+    pub fn perspective_correct(&self) -> Vertex {
+        let mut v = self.scaled(1.0 / self.t.w);
+        v.p = self.p;
+        v.t.w = 1.0;
+        v
+    }
+
+    fn scaled(&self, s: f64) -> Vertex {
+        let scale3 = |c: [f64; 3]| c.map(|c| c * s);
+        Vertex {
+            p: self.p,
+            t: Vec2D {
+                u: self.t.u * s,
+                v: self.t.v * s,
+                w: self.t.w,
+            },
+            n: &self.n * s,
+            world: &self.world * s,
+            diffuse: scale3(self.diffuse),
+            specular: scale3(self.specular),
+            color: scale3(self.color),
+        }
+    }
+}
+
+impl Interpolate for Vertex {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        Vertex {
+            p: self.p.lerp(&other.p, t),
+            t: self.t.lerp(&other.t, t),
+            n: self.n.lerp(&other.n, t),
+            world: self.world.lerp(&other.world, t),
+            diffuse: self.diffuse.lerp(&other.diffuse, t),
+            specular: self.specular.lerp(&other.specular, t),
+            color: self.color.lerp(&other.color, t),
+        }
+    }
+}
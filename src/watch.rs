@@ -0,0 +1,34 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// A file on disk that is polled for changes by comparing its modification time
+pub struct WatchedFile {
+    pub path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl WatchedFile {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let modified = modified_time(&path);
+        Self { path, modified }
+    }
+
+    // True once each time the file's modification time changes. A file that
+    // goes missing doesn't count as changed until it comes back
+    pub fn changed(&mut self) -> bool {
+        let modified = modified_time(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}